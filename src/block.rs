@@ -0,0 +1,295 @@
+use crate::interpreter::{Location, LOG_TRIGGER_ADDRESS};
+#[cfg(feature = "jit")]
+use crate::jit::CompiledCode;
+use crate::memory::{for_each_page, page_number, page_offset};
+use crate::{AccessKind, BreakData, Exception, GekkoInterpreter, GuestMemory, Instruction};
+use std::collections::HashMap;
+
+/// the maximum number of instructions of a block
+const MAX_BLOCK_LENGTH: usize = 64;
+
+/// A straight-line sequence of instructions, ending with a branch or an instruction that
+/// change the state the following ones run in (see Instruction::ends_block)
+struct Block {
+    // physical address of the first instruction
+    physical: u32,
+    instructions: Vec<Instruction>,
+    // the blocks that were run after this one, by effective address of their first
+    // instruction: usually the target of the branch, and the next block when it isn't taken
+    links: [Option<(u32, usize)>; 2],
+    // the code compiled for the block, when the JIT is enabled
+    #[cfg(feature = "jit")]
+    compiled: Option<CompiledCode>,
+}
+
+/// The blocks decoded so far, by physical address. Like the InstructionCache, the blocks are
+/// invalidated by the writes to their instructions.
+pub(crate) struct BlockCache {
+    // indexed by the identifier of the block. None for the identifiers of invalidated blocks,
+    // which are reused.
+    blocks: Vec<Option<Block>>,
+    unused: Vec<usize>,
+    by_address: HashMap<u32, usize>,
+    // the identifiers of the blocks in each physical page, up to the last page with a block
+    pages: Vec<Vec<usize>>,
+    // incremented by each invalidation, so that a block that overwrite its own instructions is
+    // stopped
+    generation: u64,
+    // the last block that was run, to link it to the next one
+    previous: Option<usize>,
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        BlockCache {
+            blocks: Vec::new(),
+            unused: Vec::new(),
+            by_address: HashMap::new(),
+            pages: Vec::new(),
+            generation: 0,
+            previous: None,
+        }
+    }
+
+    fn get(&self, id: usize) -> &Block {
+        self.blocks[id].as_ref().unwrap()
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn instructions(&self, id: usize) -> &[Instruction] {
+        &self.get(id).instructions
+    }
+
+    /// find the block starting at the effective address pc, translated to physical: first in
+    /// the links of the previous block, then by its physical address
+    fn find(&mut self, pc: u32, physical: u32) -> Option<usize> {
+        let previous = self.previous.and_then(|id| self.blocks[id].as_ref());
+        if let Some(previous) = previous {
+            for &(address, id) in previous.links.iter().flatten() {
+                // the link may be to a block that has been replaced, or the translation changed
+                match &self.blocks[id] {
+                    Some(block) if address == pc && block.physical == physical => return Some(id),
+                    _ => (),
+                };
+            }
+        };
+        let id = *self.by_address.get(&physical)?;
+        self.link(pc, id);
+        Some(id)
+    }
+
+    /// remember that the block id at the effective address pc follow the previous block
+    fn link(&mut self, pc: u32, id: usize) {
+        if let Some(Some(previous)) = self.previous.map(|previous| &mut self.blocks[previous]) {
+            // the oldest link is replaced
+            previous.links = [Some((pc, id)), previous.links[0]];
+        };
+    }
+
+    fn insert(&mut self, pc: u32, physical: u32, instructions: Vec<Instruction>) -> usize {
+        let block = Block {
+            physical,
+            instructions,
+            links: [None; 2],
+            #[cfg(feature = "jit")]
+            compiled: None,
+        };
+        let id = match self.unused.pop() {
+            Some(id) => {
+                self.blocks[id] = Some(block);
+                id
+            }
+            None => {
+                self.blocks.push(Some(block));
+                self.blocks.len() - 1
+            }
+        };
+        self.by_address.insert(physical, id);
+        let page = page_number(physical) as usize;
+        if page >= self.pages.len() {
+            self.pages.resize_with(page + 1, Vec::new);
+        };
+        self.pages[page].push(id);
+        self.link(pc, id);
+        id
+    }
+
+    /// forget the blocks overlapping the size bytes at physical
+    #[inline]
+    pub fn invalidate(&mut self, physical: u32, size: u32) {
+        for_each_page(physical, size as usize, |address, range| {
+            let page = page_number(address);
+            if let Some(ids) = self.pages.get(page as usize) {
+                if !ids.is_empty() {
+                    self.invalidate_in_page(page, address, address + range.len() as u32 - 1);
+                };
+            };
+        });
+    }
+
+    fn invalidate_in_page(&mut self, page: u32, first_byte: u32, last_byte: u32) {
+        let blocks = &mut self.blocks;
+        let mut removed = Vec::new();
+        self.pages[page as usize].retain(|&id| {
+            let block = blocks[id].as_ref().unwrap();
+            let block_last_byte = block.physical + block.instructions.len() as u32 * 4 - 1;
+            let overlap = first_byte <= block_last_byte && block.physical <= last_byte;
+            if overlap {
+                removed.push(id);
+            };
+            !overlap
+        });
+        for id in removed {
+            let block = blocks[id].take().unwrap();
+            self.by_address.remove(&block.physical);
+            self.unused.push(id);
+            self.generation += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = BlockCache::new();
+    }
+
+    #[cfg(feature = "jit")]
+    fn set_compiled(&mut self, id: usize, compiled: Option<CompiledCode>) {
+        self.blocks[id].as_mut().unwrap().compiled = compiled;
+    }
+}
+
+/// The basic block interpreter: instead of fetching and decoding each instruction, the
+/// instructions are decoded once in blocks, that are then run in a tight loop. The counter, the
+/// decrementer and the pending interrupts are checked once per block.
+impl<M: GuestMemory> GekkoInterpreter<M> {
+    /// run the block at pc, if it is no longer than budget instructions. Otherwise, or if there
+    /// is a pending interrupt, run a single step.
+    pub fn run_block(&mut self, budget: u64) -> BreakData {
+        // step enable the logging at LOG_TRIGGER_ADDRESS, which always start a block
+        if self.get_pending_interrupt().is_some() || self.register.pc == LOG_TRIGGER_ADDRESS {
+            return self.step().unwrap();
+        };
+        let pc = self.register.pc;
+        let id = match self.get_block(pc) {
+            Some(id) => id,
+            // the first instruction can't be fetched, let step raise the exception
+            None => return self.step().unwrap(),
+        };
+        let length = self.block_cache.get(id).instructions.len();
+        if length as u64 > budget {
+            return self.step().unwrap();
+        };
+        self.block_cache.previous = Some(id);
+        let generation = self.block_cache.generation;
+        self.counter += length as u64;
+        #[cfg(feature = "jit")]
+        let (executed, break_data) = match self.block_cache.get(id).compiled {
+            Some(compiled) => self.run_compiled(id, compiled, generation),
+            None => self.interpret_block(id, generation),
+        };
+        #[cfg(not(feature = "jit"))]
+        let (executed, break_data) = self.interpret_block(id, generation);
+        self.counter -= (length - executed) as u64;
+        if self.time.check_decrementer(self.counter) {
+            self.decrementer_pending = true;
+        };
+        break_data
+    }
+
+    /// interpret the block id, and return the number of instructions run with what stopped it
+    fn interpret_block(&mut self, id: usize, generation: u64) -> (usize, BreakData) {
+        let length = self.block_cache.get(id).instructions.len();
+        for index in 0..length {
+            let instruction = self.block_cache.get(id).instructions[index];
+            let address = self.register.pc;
+            let result = self.execute(instruction);
+            if let Some(break_data) = self.finish_instruction(result, address, generation) {
+                return (index + 1, break_data);
+            };
+        }
+        (length, BreakData::None)
+    }
+
+    /// take the exception raised by the instruction of a block at address, if any, and return
+    /// the break data if the block must stop after it: at an exception, something to report,
+    /// or when the block has been overwritten
+    pub(crate) fn finish_instruction(
+        &mut self,
+        result: Result<BreakData, Exception>,
+        address: u32,
+        generation: u64,
+    ) -> Option<BreakData> {
+        let break_data = match result {
+            Ok(break_data) => break_data,
+            Err(exception) => self.take_exception(exception),
+        };
+        let break_data = self.report_unhandled_mmio(break_data);
+        if break_data != BreakData::None
+            || self.register.pc != address.wrapping_add(4)
+            || self.block_cache.generation != generation
+        {
+            Some(break_data)
+        } else {
+            None
+        }
+    }
+
+    /// find or decode the block at the effective address pc
+    fn get_block(&mut self, pc: u32) -> Option<usize> {
+        let physical = match self.locate(pc, AccessKind::Fetch).ok()? {
+            Location::Physical(physical) => physical,
+            Location::LockedCache(_) => return None,
+        };
+        if let Some(id) = self.block_cache.find(pc, physical) {
+            return Some(id);
+        };
+        let mut instructions = Vec::new();
+        let mut address = pc;
+        while let Ok(instruction) = self.fetch_instruction(address) {
+            instructions.push(instruction);
+            address = address.wrapping_add(4);
+            // a block never cross a page, as the next one may be translated elsewhere
+            if instruction.ends_block()
+                || instructions.len() == MAX_BLOCK_LENGTH
+                || page_offset(address) == 0
+                || address == LOG_TRIGGER_ADDRESS
+            {
+                break;
+            };
+        }
+        if instructions.is_empty() {
+            return None;
+        };
+        // the compiled code is freed with all the blocks
+        #[cfg(feature = "jit")]
+        self.reclaim_jit_code();
+        let id = self.block_cache.insert(pc, physical, instructions);
+        #[cfg(feature = "jit")]
+        {
+            let compiled = self.compile_block(id);
+            self.block_cache.set_compiled(id, compiled);
+        }
+        Some(id)
+    }
+}
+
+#[test]
+fn test_block_cache() {
+    let mut cache = BlockCache::new();
+    let first = cache.insert(0x8000_1000, 0x1000, vec![Instruction::Sync; 4]);
+    cache.previous = Some(first);
+    let second = cache.insert(0x8000_2000, 0x2000, vec![Instruction::Isync]);
+    assert_eq!(cache.find(0x8000_2000, 0x2000), Some(second));
+    // the translation changed
+    assert_eq!(cache.find(0x8000_2000, 0x3000), None);
+    cache.invalidate(0x100C, 4);
+    assert_eq!(cache.find(0x8000_1000, 0x1000), None);
+    assert_eq!(cache.find(0x8000_2000, 0x2000), Some(second));
+    assert_eq!(cache.generation, 1);
+    cache.invalidate(0x1010, 0x1000);
+    assert_eq!(cache.find(0x8000_2000, 0x2000), None);
+}
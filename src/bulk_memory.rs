@@ -0,0 +1,128 @@
+use crate::memory::{page_offset, try_for_each_page};
+use crate::mmu::AccessKind;
+use crate::{Exception, GekkoInterpreter, GuestMemory, PAGE_SIZE};
+
+/// A value stored in big endian in the guest memory, that can be read and written in arrays
+pub trait BigEndian: Copy {
+    const SIZE: usize;
+
+    fn from_be_slice(bytes: &[u8]) -> Self;
+
+    fn to_be_slice(self, bytes: &mut [u8]);
+}
+
+macro_rules! impl_big_endian {
+    ($($value:ty),*) => {
+        $(
+            impl BigEndian for $value {
+                const SIZE: usize = std::mem::size_of::<$value>();
+
+                #[inline]
+                fn from_be_slice(bytes: &[u8]) -> Self {
+                    let mut buffer = [0; std::mem::size_of::<$value>()];
+                    buffer.copy_from_slice(bytes);
+                    <$value>::from_be_bytes(buffer)
+                }
+
+                #[inline]
+                fn to_be_slice(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_big_endian!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Accesses to ranges of the effective address space, for the HLE functions and the embedder.
+/// Like try_read_u32 and the other ones, they return a DSI if a byte isn't mapped. A write that
+/// fails may have been partially done. They are split at the page boundaries, as each page may
+/// be translated elsewhere.
+impl<M: GuestMemory> GekkoInterpreter<M> {
+    /// fill buffer with the bytes at address
+    pub fn read_bytes(&self, address: u32, buffer: &mut [u8]) -> Result<(), Exception> {
+        try_for_each_page(address, buffer.len(), |address, range| {
+            self.read_guest(address, &mut buffer[range], AccessKind::Load)
+                .map_err(|fault| Exception::dsi(address, false, fault))
+        })
+    }
+
+    /// write data at address, like a file loaded in memory
+    pub fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), Exception> {
+        try_for_each_page(address, data.len(), |address, range| {
+            self.write_guest(address, &data[range])
+                .map_err(|fault| Exception::dsi(address, true, fault))
+        })
+    }
+
+    /// copy length bytes from source to destination. They may overlap, like with memmove.
+    pub fn copy(&mut self, destination: u32, source: u32, length: usize) -> Result<(), Exception> {
+        let mut buffer = vec![0; length];
+        self.read_bytes(source, &mut buffer)?;
+        self.write_bytes(destination, &buffer)
+    }
+
+    /// set the length bytes at address to value, like memset
+    pub fn fill(&mut self, address: u32, value: u8, length: usize) -> Result<(), Exception> {
+        let buffer = [value; PAGE_SIZE];
+        try_for_each_page(address, length, |address, range| {
+            self.write_guest(address, &buffer[..range.len()])
+                .map_err(|fault| Exception::dsi(address, true, fault))
+        })
+    }
+
+    /// read the null terminated string at address, without its terminator. At most max_length
+    /// bytes are read: the string is truncated if there is no terminator before.
+    pub fn read_cstring(&self, address: u32, max_length: usize) -> Result<Vec<u8>, Exception> {
+        let mut string = Vec::new();
+        let mut part = [0; PAGE_SIZE];
+        let mut address = address;
+        while string.len() < max_length {
+            // read up to the end of the page, as the next one may not be mapped
+            let length = (PAGE_SIZE - page_offset(address)).min(max_length - string.len());
+            let part = &mut part[..length];
+            self.read_bytes(address, part)?;
+            if let Some(end) = part.iter().position(|byte| *byte == 0) {
+                string.extend_from_slice(&part[..end]);
+                break;
+            };
+            string.extend_from_slice(part);
+            address = address.wrapping_add(length as u32);
+        }
+        Ok(string)
+    }
+
+    /// write string at address, followed by a null terminator
+    pub fn write_cstring(&mut self, address: u32, string: &[u8]) -> Result<(), Exception> {
+        self.write_bytes(address, string)?;
+        self.try_write_u8(address.wrapping_add(string.len() as u32), 0)
+    }
+
+    /// read values.len() big endian values at address, like the content of a struct
+    pub fn read_array<T: BigEndian>(
+        &self,
+        address: u32,
+        values: &mut [T],
+    ) -> Result<(), Exception> {
+        let mut buffer = vec![0; values.len() * T::SIZE];
+        self.read_bytes(address, &mut buffer)?;
+        for (value, bytes) in values.iter_mut().zip(buffer.chunks_exact(T::SIZE)) {
+            *value = T::from_be_slice(bytes);
+        }
+        Ok(())
+    }
+
+    /// write values at address, in big endian
+    pub fn write_array<T: BigEndian>(
+        &mut self,
+        address: u32,
+        values: &[T],
+    ) -> Result<(), Exception> {
+        let mut buffer = vec![0; values.len() * T::SIZE];
+        for (value, bytes) in values.iter().zip(buffer.chunks_exact_mut(T::SIZE)) {
+            value.to_be_slice(bytes);
+        }
+        self.write_bytes(address, &buffer)
+    }
+}
@@ -0,0 +1,146 @@
+use crate::mmu::TranslationFault;
+
+/// An architectural exception of the Gekko. When one is taken, the state is saved to
+/// SRR0/SRR1, the MSR is updated and the execution continue at its vector.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Exception {
+    SystemReset,
+    MachineCheck,
+    // data storage interrupt: an access to address couldn't be performed, because it isn't
+    // translated or, if protection is set, because the protection forbid it
+    Dsi {
+        address: u32,
+        store: bool,
+        protection: bool,
+    },
+    // instruction storage interrupt: the instruction at address couldn't be fetched
+    Isi {
+        address: u32,
+    },
+    External,
+    // the access to address isn't aligned. dsisr identify the instruction, see alignment.
+    Alignment {
+        address: u32,
+        dsisr: u32,
+    },
+    Program(ProgramReason),
+    FloatingPointUnavailable,
+    Decrementer,
+    SystemCall,
+    Trace,
+    PerformanceMonitor,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProgramReason {
+    FloatingPoint,
+    IllegalInstruction,
+    PrivilegedInstruction,
+    Trap,
+}
+
+/// The kind of an exception, without the data attached to it.
+/// Used to select which exceptions are reported to the embedder.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ExceptionKind {
+    SystemReset,
+    MachineCheck,
+    Dsi,
+    Isi,
+    External,
+    Alignment,
+    Program,
+    FloatingPointUnavailable,
+    Decrementer,
+    SystemCall,
+    Trace,
+    PerformanceMonitor,
+}
+
+impl Exception {
+    /// the alignment exception of an access to address, by the instruction with the primary
+    /// opcode and, for the X-form ones, the extended opcode. The DSISR hold the bits of the
+    /// instruction that identify it, then its registers rD/rS and rA.
+    pub fn alignment(
+        address: u32,
+        primary: u32,
+        extended: Option<u32>,
+        gpr_d: u8,
+        gpr_a: u8,
+    ) -> Exception {
+        let index = match extended {
+            // bits 29-30, 25 and 21-24 of the instruction
+            Some(extended) => {
+                ((extended & 0x3) << 5) | (((extended >> 5) & 1) << 4) | ((extended >> 6) & 0xF)
+            }
+            // 00, then bits 5 and 1-4 of the instruction
+            None => ((primary & 1) << 4) | ((primary >> 1) & 0xF),
+        };
+        Exception::Alignment {
+            address,
+            dsisr: (index << 10) | ((gpr_d as u32) << 5) | gpr_a as u32,
+        }
+    }
+
+    /// the DSI of an access to address that couldn't be translated because of fault
+    pub(crate) fn dsi(address: u32, store: bool, fault: TranslationFault) -> Exception {
+        Exception::Dsi {
+            address,
+            store,
+            protection: fault == TranslationFault::Protection,
+        }
+    }
+
+    pub fn kind(&self) -> ExceptionKind {
+        match self {
+            Exception::SystemReset => ExceptionKind::SystemReset,
+            Exception::MachineCheck => ExceptionKind::MachineCheck,
+            Exception::Dsi { .. } => ExceptionKind::Dsi,
+            Exception::Isi { .. } => ExceptionKind::Isi,
+            Exception::External => ExceptionKind::External,
+            Exception::Alignment { .. } => ExceptionKind::Alignment,
+            Exception::Program(_) => ExceptionKind::Program,
+            Exception::FloatingPointUnavailable => ExceptionKind::FloatingPointUnavailable,
+            Exception::Decrementer => ExceptionKind::Decrementer,
+            Exception::SystemCall => ExceptionKind::SystemCall,
+            Exception::Trace => ExceptionKind::Trace,
+            Exception::PerformanceMonitor => ExceptionKind::PerformanceMonitor,
+        }
+    }
+
+    /// physical address of the handler of this exception, when MSR[IP] is 0
+    pub fn vector(&self) -> u32 {
+        match self.kind() {
+            ExceptionKind::SystemReset => 0x100,
+            ExceptionKind::MachineCheck => 0x200,
+            ExceptionKind::Dsi => 0x300,
+            ExceptionKind::Isi => 0x400,
+            ExceptionKind::External => 0x500,
+            ExceptionKind::Alignment => 0x600,
+            ExceptionKind::Program => 0x700,
+            ExceptionKind::FloatingPointUnavailable => 0x800,
+            ExceptionKind::Decrementer => 0x900,
+            ExceptionKind::SystemCall => 0xC00,
+            ExceptionKind::Trace => 0xD00,
+            ExceptionKind::PerformanceMonitor => 0xF00,
+        }
+    }
+
+    /// the exception specific bits that are set in SRR1 when this exception is taken
+    pub fn srr1_flags(&self) -> u32 {
+        match self {
+            // the effective address couldn't be translated
+            Exception::Isi { .. } => 0x4000_0000,
+            Exception::Program(ProgramReason::FloatingPoint) => 0x0010_0000,
+            Exception::Program(ProgramReason::IllegalInstruction) => 0x0008_0000,
+            Exception::Program(ProgramReason::PrivilegedInstruction) => 0x0004_0000,
+            Exception::Program(ProgramReason::Trap) => 0x0002_0000,
+            _ => 0,
+        }
+    }
+
+    /// true if SRR0 should point to the next instruction rather than the one that caused it
+    pub fn resume_after_instruction(&self) -> bool {
+        matches!(self, Exception::SystemCall)
+    }
+}
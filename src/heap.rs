@@ -0,0 +1,281 @@
+use crate::interpreter::Location;
+use crate::{AccessKind, GekkoInterpreter, GuestMemory, MEM1_SIZE};
+use std::collections::{BTreeMap, HashMap};
+
+/// the alignment of the blocks returned by OSAllocFromHeap, and the granularity of the sizes of
+/// every block, so that they never share a cache block
+pub const HEAP_ALIGNMENT: u32 = 32;
+
+/// The usage of a GuestHeap, in bytes
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct HeapStats {
+    pub size: u32,
+    pub used: u32,
+    pub free: u32,
+    pub allocations: usize,
+    pub free_blocks: usize,
+    pub largest_free_block: u32,
+}
+
+impl HeapStats {
+    /// the part of the free memory that isn't in the largest free block: 0 if it is all in one
+    /// block, close to 1 if it is split in many small ones
+    pub fn fragmentation(&self) -> f32 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_block as f32 / self.free as f32
+        }
+    }
+}
+
+#[inline]
+fn align_up(value: u32, alignment: u32) -> Option<u32> {
+    Some(value.checked_add(alignment - 1)? & !(alignment - 1))
+}
+
+/// A first-fit allocator of guest memory, whose bookkeeping is kept on the host: the memory
+/// of the arena is never read or written by it.
+pub struct GuestHeap {
+    start: u32,
+    size: u32,
+    // the free blocks, by address. Two of them are never contiguous.
+    free: BTreeMap<u32, u32>,
+    // the size of the allocated blocks, by address
+    allocated: HashMap<u32, u32>,
+}
+
+impl GuestHeap {
+    /// a heap managing the size bytes at the guest address start, or None if they wrap around
+    /// the end of the address space
+    pub fn new(start: u32, size: u32) -> Option<GuestHeap> {
+        start.checked_add(size)?;
+        let mut free = BTreeMap::new();
+        if size != 0 {
+            free.insert(start, size);
+        };
+        Some(GuestHeap {
+            start,
+            size,
+            free,
+            allocated: HashMap::new(),
+        })
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// allocate a block of at least size bytes, whose address is a multiple of alignment (a
+    /// power of two, at least HEAP_ALIGNMENT is used). Return its address, or None if there is
+    /// no free block large enough.
+    pub fn allocate(&mut self, size: u32, alignment: u32) -> Option<u32> {
+        assert!(alignment.is_power_of_two());
+        let alignment = alignment.max(HEAP_ALIGNMENT);
+        let size = align_up(size.max(1), HEAP_ALIGNMENT)?;
+        let (block, block_size, address) = self.free.iter().find_map(|(&block, &block_size)| {
+            let address = align_up(block, alignment)?;
+            let padding = address - block;
+            (padding <= block_size && block_size - padding >= size)
+                .then_some((block, block_size, address))
+        })?;
+        self.free.remove(&block);
+        if address > block {
+            self.free.insert(block, address - block);
+        };
+        let remaining = block_size - (address - block) - size;
+        if remaining != 0 {
+            self.free.insert(address + size, remaining);
+        };
+        self.allocated.insert(address, size);
+        Some(address)
+    }
+
+    /// free the block allocated at address. Return its size, or None if there is no block
+    /// allocated there.
+    pub fn free(&mut self, address: u32) -> Option<u32> {
+        let size = self.allocated.remove(&address)?;
+        let (mut start, mut end) = (address, address + size);
+        // merge with the free blocks just before and after
+        if let Some((&previous, &previous_size)) = self.free.range(..address).next_back() {
+            if previous + previous_size == address {
+                self.free.remove(&previous);
+                start = previous;
+            };
+        };
+        if let Some(next_size) = self.free.remove(&end) {
+            end += next_size;
+        };
+        self.free.insert(start, end - start);
+        Some(size)
+    }
+
+    /// the size of the block allocated at address
+    pub fn allocation_size(&self, address: u32) -> Option<u32> {
+        self.allocated.get(&address).copied()
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let free = self.free.values().sum();
+        HeapStats {
+            size: self.size,
+            used: self.size - free,
+            free,
+            allocations: self.allocated.len(),
+            free_blocks: self.free.len(),
+            largest_free_block: self.free.values().copied().max().unwrap_or(0),
+        }
+    }
+}
+
+/// The heaps of the guest, replacing the ones of the SDK. A heap is identified by its index in
+/// GekkoInterpreter::heaps, like the handles of OSCreateHeap.
+impl<M: GuestMemory> GekkoInterpreter<M> {
+    /// reserve the size bytes of MEM1 at the guest address start for a new heap, and return its
+    /// handle. Return None if they aren't all mapped to MEM1, or if they overlap another heap.
+    pub fn create_heap(&mut self, start: u32, size: u32) -> Option<usize> {
+        let heap = GuestHeap::new(start, size).filter(|_| size != 0)?;
+        let first = self.mem1_address(start)?;
+        let last = self.mem1_address(start + size - 1)?;
+        if last.checked_sub(first) != Some(size - 1) {
+            return None;
+        };
+        if self
+            .heaps
+            .iter()
+            .flatten()
+            .any(|other| start < other.start() + other.size() && other.start() < start + size)
+        {
+            return None;
+        };
+        Some(match self.heaps.iter().position(Option::is_none) {
+            Some(handle) => {
+                self.heaps[handle] = Some(heap);
+                handle
+            }
+            None => {
+                self.heaps.push(Some(heap));
+                self.heaps.len() - 1
+            }
+        })
+    }
+
+    // the physical address of the byte of MEM1 the guest address is translated to, if any
+    fn mem1_address(&self, address: u32) -> Option<u32> {
+        match self.locate(address, AccessKind::Store).ok()? {
+            Location::Physical(physical)
+                if (physical as usize) < MEM1_SIZE && self.memory.read_u8(physical).is_ok() =>
+            {
+                Some(physical)
+            }
+            _ => None,
+        }
+    }
+
+    /// remove a heap. The host values attached to its blocks are dropped.
+    pub fn destroy_heap(&mut self, handle: usize) -> Option<GuestHeap> {
+        let heap = self.heaps.get_mut(handle)?.take()?;
+        self.host_data.release(heap.start(), heap.size());
+        Some(heap)
+    }
+
+    pub fn alloc_from_heap(&mut self, handle: usize, size: u32) -> Option<u32> {
+        self.heaps
+            .get_mut(handle)?
+            .as_mut()?
+            .allocate(size, HEAP_ALIGNMENT)
+    }
+
+    /// free a block. The host values attached to it are dropped. Return false if it isn't a
+    /// block of this heap.
+    pub fn free_to_heap(&mut self, handle: usize, address: u32) -> bool {
+        let heap = match self.heaps.get_mut(handle) {
+            Some(Some(heap)) => heap,
+            _ => return false,
+        };
+        match heap.free(address) {
+            Some(size) => {
+                self.host_data.release(address, size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn heap_stats(&self, handle: usize) -> Option<HeapStats> {
+        self.heaps.get(handle)?.as_ref().map(GuestHeap::stats)
+    }
+
+    // the functions below replace the ones of the SDK: they take their arguments from r3 and
+    // the following registers, return in r3 and jump to lr, as if the function had run
+
+    /// OSCreateHeap(void* start, void* end) -> OSHeapHandle, returning -1 on failure
+    pub fn os_create_heap(&mut self) {
+        let start = self.register.get_gpr(3);
+        let end = self.register.get_gpr(4);
+        // the SDK round the arena to 32 bytes blocks
+        let aligned_start = align_up(start, HEAP_ALIGNMENT).unwrap_or(end);
+        let aligned_end = end & !(HEAP_ALIGNMENT - 1);
+        let handle = self.create_heap(aligned_start, aligned_end.saturating_sub(aligned_start));
+        self.register
+            .set_gpr(3, handle.map_or(u32::MAX, |handle| handle as u32));
+        self.return_from_call();
+    }
+
+    /// OSDestroyHeap(OSHeapHandle heap)
+    pub fn os_destroy_heap(&mut self) {
+        let handle = self.register.get_gpr(3) as usize;
+        self.destroy_heap(handle);
+        self.return_from_call();
+    }
+
+    /// OSAllocFromHeap(OSHeapHandle heap, u32 size) -> void*, returning NULL on failure
+    pub fn os_alloc_from_heap(&mut self) {
+        let handle = self.register.get_gpr(3) as usize;
+        let size = self.register.get_gpr(4);
+        let address = self.alloc_from_heap(handle, size).unwrap_or(0);
+        self.register.set_gpr(3, address);
+        self.return_from_call();
+    }
+
+    /// OSFreeToHeap(OSHeapHandle heap, void* ptr)
+    pub fn os_free_to_heap(&mut self) {
+        let handle = self.register.get_gpr(3) as usize;
+        let address = self.register.get_gpr(4);
+        self.free_to_heap(handle, address);
+        self.return_from_call();
+    }
+
+    fn return_from_call(&mut self) {
+        self.register.pc = self.register.lr;
+    }
+}
+
+#[test]
+fn test_guest_heap() {
+    assert!(GuestHeap::new(0xFFFF_F000, 0x2000).is_none());
+    let mut heap = GuestHeap::new(0x8040_0000, 0x1000).unwrap();
+    let first = heap.allocate(0x10, 32).unwrap();
+    assert_eq!(first, 0x8040_0000);
+    let second = heap.allocate(0x100, 0x100).unwrap();
+    assert_eq!(second, 0x8040_0100);
+    assert_eq!(heap.allocation_size(first), Some(0x20));
+    // the padding before the aligned block stay free
+    let stats = heap.stats();
+    assert_eq!(stats.used, 0x120);
+    assert_eq!(stats.free_blocks, 2);
+    assert_eq!(stats.largest_free_block, 0xE00);
+    assert!(stats.fragmentation() > 0.0);
+    assert_eq!(heap.allocate(0x1000, 32), None);
+
+    assert_eq!(heap.free(first), Some(0x20));
+    assert_eq!(heap.free(first), None);
+    assert_eq!(heap.free(second), Some(0x100));
+    let stats = heap.stats();
+    assert_eq!((stats.free, stats.free_blocks), (0x1000, 1));
+    assert_eq!(stats.fragmentation(), 0.0);
+}
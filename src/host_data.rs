@@ -0,0 +1,422 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// What a host value is attached to
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum HostKey {
+    // the effective address of a guest object. It follows the object when it is freed or moved
+    // by realloc, as long as the heap call HostData::release and HostData::relocate.
+    Address(u32),
+    // an identifier from HostData::allocate_handle, usually stored in an unused field of the
+    // guest object, so it survives copies the heap doesn't know about
+    Handle(u32),
+}
+
+/// An error while loading the data saved by HostData::save
+#[derive(Debug, PartialEq, Clone)]
+pub enum HostDataError {
+    // the data ended in the middle of a value
+    Truncated,
+    // a key is neither an address (0) nor a handle (1)
+    InvalidKey(u8),
+    // no type has been registered with this name by HostData::register_serializer
+    UnknownSlot(String),
+    // the load function of the slot refused a value
+    InvalidValue(String),
+}
+
+/// how the values of a type are saved: a name identifying the slot in the save, and the
+/// conversion of a value from and to bytes
+struct Serializer<T> {
+    name: &'static str,
+    save: fn(&T) -> Vec<u8>,
+    load: fn(&[u8]) -> Option<T>,
+}
+
+struct Slot<T> {
+    values: HashMap<HostKey, T>,
+    serializer: Option<Serializer<T>>,
+}
+
+// the operations done on every slot, whatever the type of its values
+trait AnySlot {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn release(&mut self, start: u32, size: u32);
+    fn relocate(&mut self, old: u32, old_size: u32, new: u32, new_size: u32);
+    fn clear(&mut self);
+    fn name(&self) -> Option<&'static str>;
+    fn save(&self, output: &mut Vec<u8>);
+    // parse the values saved by save, to be passed to replace once the whole save is parsed
+    fn load(&self, input: &mut &[u8]) -> Result<Box<dyn Any>, HostDataError>;
+    fn replace(&mut self, values: Box<dyn Any>);
+}
+
+impl<T: 'static> AnySlot for Slot<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn release(&mut self, start: u32, size: u32) {
+        self.values.retain(|key, _| match key {
+            HostKey::Address(address) => address.wrapping_sub(start) >= size,
+            HostKey::Handle(_) => true,
+        });
+    }
+
+    fn relocate(&mut self, old: u32, old_size: u32, new: u32, new_size: u32) {
+        let moved: Vec<u32> = self
+            .values
+            .keys()
+            .filter_map(|key| match key {
+                HostKey::Address(address) if address.wrapping_sub(old) < old_size => Some(*address),
+                _ => None,
+            })
+            .collect();
+        let moved: Vec<(u32, T)> = moved
+            .into_iter()
+            .map(|address| {
+                (
+                    address,
+                    self.values.remove(&HostKey::Address(address)).unwrap(),
+                )
+            })
+            .collect();
+        for (address, value) in moved {
+            let offset = address.wrapping_sub(old);
+            if offset < new_size {
+                self.values
+                    .insert(HostKey::Address(new.wrapping_add(offset)), value);
+            };
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        self.serializer.as_ref().map(|serializer| serializer.name)
+    }
+
+    fn save(&self, output: &mut Vec<u8>) {
+        let serializer = match &self.serializer {
+            Some(serializer) => serializer,
+            None => return,
+        };
+        write_bytes(output, serializer.name.as_bytes());
+        output.extend_from_slice(&(self.values.len() as u32).to_be_bytes());
+        // sorted, so that the same values are always saved the same way
+        let mut values: Vec<_> = self.values.iter().collect();
+        values.sort_unstable_by_key(|(key, _)| **key);
+        for (key, value) in values {
+            let (kind, key) = match key {
+                HostKey::Address(address) => (0, address),
+                HostKey::Handle(handle) => (1, handle),
+            };
+            output.push(kind);
+            output.extend_from_slice(&key.to_be_bytes());
+            write_bytes(output, &(serializer.save)(value));
+        }
+    }
+
+    fn load(&self, input: &mut &[u8]) -> Result<Box<dyn Any>, HostDataError> {
+        let serializer = self.serializer.as_ref().unwrap();
+        let mut values = HashMap::new();
+        for _ in 0..read_u32(input)? {
+            let key = match read(input, 1)?[0] {
+                0 => HostKey::Address(read_u32(input)?),
+                1 => HostKey::Handle(read_u32(input)?),
+                kind => return Err(HostDataError::InvalidKey(kind)),
+            };
+            let value = (serializer.load)(read_bytes(input)?)
+                .ok_or_else(|| HostDataError::InvalidValue(serializer.name.to_string()))?;
+            values.insert(key, value);
+        }
+        Ok(Box::new(values))
+    }
+
+    fn replace(&mut self, values: Box<dyn Any>) {
+        self.values = *values.downcast().unwrap();
+    }
+}
+
+// the save is a sequence of big endian u32, and of byte strings prefixed by their length
+fn write_bytes(output: &mut Vec<u8>, bytes: &[u8]) {
+    output.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    output.extend_from_slice(bytes);
+}
+
+fn read<'a>(input: &mut &'a [u8], length: usize) -> Result<&'a [u8], HostDataError> {
+    if input.len() < length {
+        return Err(HostDataError::Truncated);
+    };
+    let (read, rest) = input.split_at(length);
+    *input = rest;
+    Ok(read)
+}
+
+fn read_u32(input: &mut &[u8]) -> Result<u32, HostDataError> {
+    let bytes = read(input, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8]) -> Result<&'a [u8], HostDataError> {
+    let length = read_u32(input)? as usize;
+    read(input, length)
+}
+
+/// Rust values attached to guest objects, like the host side of a file handle or a texture.
+/// There is a slot per type of value, mapping HostKey to values of this type. The values attached
+/// to an address are dropped when the heap release it, and follow it when it is reallocated.
+#[derive(Default)]
+pub struct HostData {
+    slots: HashMap<TypeId, Box<dyn AnySlot>>,
+    next_handle: u32,
+}
+
+impl HostData {
+    fn slot<T: 'static>(&self) -> Option<&Slot<T>> {
+        self.slots
+            .get(&TypeId::of::<T>())
+            .map(|slot| slot.as_any().downcast_ref().unwrap())
+    }
+
+    fn slot_mut<T: 'static>(&mut self) -> &mut Slot<T> {
+        self.slots
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| {
+                Box::new(Slot::<T> {
+                    values: HashMap::new(),
+                    serializer: None,
+                })
+            })
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+
+    /// a new handle, never returned before. It is never 0, so 0 can mean no handle.
+    pub fn allocate_handle(&mut self) -> u32 {
+        self.next_handle += 1;
+        self.next_handle
+    }
+
+    /// attach value to key, returning the value of the same type it replaced
+    pub fn insert<T: 'static>(&mut self, key: HostKey, value: T) -> Option<T> {
+        self.slot_mut().values.insert(key, value)
+    }
+
+    pub fn get<T: 'static>(&self, key: HostKey) -> Option<&T> {
+        self.slot()?.values.get(&key)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, key: HostKey) -> Option<&mut T> {
+        self.slot_mut().values.get_mut(&key)
+    }
+
+    pub fn remove<T: 'static>(&mut self, key: HostKey) -> Option<T> {
+        self.slot_mut().values.remove(&key)
+    }
+
+    /// drop the values of every type attached to an address in the size bytes at start. To be
+    /// called when the guest free memory.
+    pub fn release(&mut self, start: u32, size: u32) {
+        for slot in self.slots.values_mut() {
+            slot.release(start, size);
+        }
+    }
+
+    /// move the values attached to an address in the old_size bytes at old to the same offset
+    /// from new. The ones that don't fit in the new_size bytes at new are dropped. To be called
+    /// when the guest reallocate memory, instead of release.
+    pub fn relocate(&mut self, old: u32, old_size: u32, new: u32, new_size: u32) {
+        for slot in self.slots.values_mut() {
+            slot.relocate(old, old_size, new, new_size);
+        }
+    }
+
+    /// drop every value, like on a reboot
+    pub fn clear(&mut self) {
+        for slot in self.slots.values_mut() {
+            slot.clear();
+        }
+        self.next_handle = 0;
+    }
+
+    /// save the values of type T in the save-states under name, with save and load converting
+    /// them from and to bytes. The values of the types without serializer aren't saved, and are
+    /// left untouched by HostData::load.
+    pub fn register_serializer<T: 'static>(
+        &mut self,
+        name: &'static str,
+        save: fn(&T) -> Vec<u8>,
+        load: fn(&[u8]) -> Option<T>,
+    ) {
+        assert!(
+            self.slots
+                .iter()
+                .all(|(id, slot)| *id == TypeId::of::<T>() || slot.name() != Some(name)),
+            "the name {} is already used",
+            name
+        );
+        self.slot_mut::<T>().serializer = Some(Serializer { name, save, load });
+    }
+
+    /// the values of the types with a serializer, to be stored in a save-state. The same values
+    /// always give the same bytes.
+    pub fn save(&self) -> Vec<u8> {
+        let mut output = self.next_handle.to_be_bytes().to_vec();
+        let mut saved: Vec<_> = self
+            .slots
+            .values()
+            .filter(|slot| slot.name().is_some())
+            .collect();
+        saved.sort_unstable_by_key(|slot| slot.name());
+        output.extend_from_slice(&(saved.len() as u32).to_be_bytes());
+        for slot in saved {
+            slot.save(&mut output);
+        }
+        output
+    }
+
+    /// replace the values of the types with a serializer by the ones of a save. The serializers
+    /// must be registered with the same names as when saving. Nothing is changed if the save
+    /// can't be loaded.
+    pub fn load(&mut self, mut input: &[u8]) -> Result<(), HostDataError> {
+        let input = &mut input;
+        let next_handle = read_u32(input)?;
+        let mut loaded = Vec::new();
+        for _ in 0..read_u32(input)? {
+            let name = String::from_utf8_lossy(read_bytes(input)?).into_owned();
+            let (id, slot) = self
+                .slots
+                .iter()
+                .find(|(_, slot)| slot.name() == Some(&name))
+                .ok_or(HostDataError::UnknownSlot(name))?;
+            loaded.push((*id, slot.load(input)?));
+        }
+
+        self.next_handle = next_handle;
+        for slot in self.slots.values_mut() {
+            if slot.name().is_some() {
+                slot.clear();
+            };
+        }
+        for (id, values) in loaded {
+            self.slots.get_mut(&id).unwrap().replace(values);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_host_data() {
+    #[derive(Debug, PartialEq)]
+    struct File(String);
+    let mut data = HostData::default();
+    data.register_serializer::<File>(
+        "file",
+        |file| file.0.as_bytes().to_vec(),
+        |bytes| String::from_utf8(bytes.to_vec()).ok().map(File),
+    );
+    data.insert(HostKey::Address(0x8000_1000), File("a.bin".into()));
+    data.insert(HostKey::Address(0x8000_1010), 5u32);
+    let handle = data.allocate_handle();
+    data.insert(HostKey::Handle(handle), File("b.bin".into()));
+    assert_eq!(data.get::<u32>(HostKey::Address(0x8000_1000)), None);
+
+    // realloc of 0x20 bytes at 0x8000_1000 to 0x20 and then 0x10 bytes at 0x8000_2000
+    data.relocate(0x8000_1000, 0x20, 0x8000_2000, 0x20);
+    assert_eq!(
+        data.get(HostKey::Address(0x8000_2000)),
+        Some(&File("a.bin".into()))
+    );
+    assert_eq!(data.get(HostKey::Address(0x8000_2010)), Some(&5u32));
+    assert_eq!(data.get::<File>(HostKey::Address(0x8000_1000)), None);
+    data.relocate(0x8000_2000, 0x20, 0x8000_2000, 0x10);
+    assert_eq!(data.get::<u32>(HostKey::Address(0x8000_2010)), None);
+    data.insert(HostKey::Address(0x8000_2010), 5u32);
+    let save = data.save();
+    data.release(0x8000_2000, 0x20);
+    assert_eq!(data.get::<File>(HostKey::Address(0x8000_2000)), None);
+    assert_eq!(data.get::<u32>(HostKey::Address(0x8000_2010)), None);
+
+    data.load(&save).unwrap();
+    assert_eq!(
+        data.get(HostKey::Address(0x8000_2000)),
+        Some(&File("a.bin".into()))
+    );
+    assert_eq!(
+        data.get(HostKey::Handle(handle)),
+        Some(&File("b.bin".into()))
+    );
+    // without serializer
+    assert_eq!(data.get::<u32>(HostKey::Address(0x8000_2010)), None);
+    assert_eq!(data.allocate_handle(), handle + 1);
+    assert_eq!(
+        data.load(&save[..save.len() - 1]),
+        Err(HostDataError::Truncated)
+    );
+}
+
+#[test]
+fn test_host_data_failed_load() {
+    let register = |data: &mut HostData| {
+        data.register_serializer::<u32>(
+            "u32",
+            |value| value.to_be_bytes().to_vec(),
+            |bytes| read_u32(&mut &bytes[..]).ok(),
+        );
+        data.register_serializer::<u16>(
+            "u16",
+            |value| value.to_be_bytes().to_vec(),
+            |bytes| Some(u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?])),
+        );
+    };
+    let mut data = HostData::default();
+    register(&mut data);
+    for address in 0..8 {
+        data.insert(HostKey::Address(address), address);
+        data.insert(HostKey::Handle(address), address as u16);
+    }
+    let save = data.save();
+    // the same values, inserted in another order, are saved the same way
+    let mut copy = HostData::default();
+    register(&mut copy);
+    for address in (0..8).rev() {
+        copy.insert(HostKey::Handle(address), address as u16);
+        copy.insert(HostKey::Address(address), address);
+    }
+    assert_eq!(copy.save(), save);
+
+    data.allocate_handle();
+    data.insert(HostKey::Address(0x10), 0x10u32);
+    let check = |data: &HostData| {
+        assert_eq!(data.get(HostKey::Address(0x10)), Some(&0x10u32));
+        assert_eq!(data.get(HostKey::Handle(7)), Some(&7u16));
+    };
+    // truncated in the last slot, once the first one has been parsed
+    assert_eq!(
+        data.load(&save[..save.len() - 1]),
+        Err(HostDataError::Truncated)
+    );
+    check(&data);
+    // the kind of the first key of the first slot, "u16"
+    let mut invalid = save.clone();
+    invalid[4 + 4 + 4 + 3 + 4] = 2;
+    assert_eq!(data.load(&invalid), Err(HostDataError::InvalidKey(2)));
+    check(&data);
+    assert_eq!(
+        HostData::default().load(&save),
+        Err(HostDataError::UnknownSlot("u16".into()))
+    );
+    assert_eq!(data.allocate_handle(), 2);
+    data.load(&save).unwrap();
+    assert_eq!(data.get::<u32>(HostKey::Address(0x10)), None);
+    assert_eq!(data.allocate_handle(), 1);
+}
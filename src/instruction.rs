@@ -1,7 +1,7 @@
 use crate::util::{extend_sign_16, extend_sign_32, get_bit_section, get_bit_value};
 use crate::{Spr, Tbr};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum Instruction {
     Addx(u8, u8, u8, bool, bool),      //rD, rA, rB, OE, Rc
@@ -51,15 +51,242 @@ pub enum Instruction {
     Stfdu(u8, u8, i16),                //frS, rA, d
     Stfd(u8, u8, i16),                 //frS, rA, d
     Psq_st(u8, u8, bool, u8, i16),     //frS, rA, W, I, d
-    Psq_l(u8, u8, bool, u8, i16),      //frS, rA, W, I, d
+    Psq_l(u8, u8, bool, u8, i16),      //frD, rA, W, I, d
+    Psq_stu(u8, u8, bool, u8, i16),    //frS, rA, W, I, d
+    Psq_lu(u8, u8, bool, u8, i16),     //frD, rA, W, I, d
+    Psq_stx(u8, u8, u8, bool, u8),     //frS, rA, rB, W, I
+    Psq_stux(u8, u8, u8, bool, u8),    //frS, rA, rB, W, I
+    Psq_lx(u8, u8, u8, bool, u8),      //frD, rA, rB, W, I
+    Psq_lux(u8, u8, u8, bool, u8),     //frD, rA, rB, W, I
     Rlwimix(u8, u8, u8, u8, u8, bool), //rS, rA, SH, MB, ME, Rc
+    Ps_cmpu0(u8, u8, u8),              //crfD, frA, frB
+    Ps_cmpo0(u8, u8, u8),              //crfD, frA, frB
+    Ps_cmpu1(u8, u8, u8),              //crfD, frA, frB
+    Ps_cmpo1(u8, u8, u8),              //crfD, frA, frB
+    Ps_negx(u8, u8, bool),             //frD, frB, Rc
+    Ps_mrx(u8, u8, bool),              //frD, frB, Rc
+    Ps_nabsx(u8, u8, bool),            //frD, frB, Rc
+    Ps_absx(u8, u8, bool),             //frD, frB, Rc
+    Ps_merge00x(u8, u8, u8, bool),     //frD, frA, frB, Rc
+    Ps_merge01x(u8, u8, u8, bool),     //frD, frA, frB, Rc
+    Ps_merge10x(u8, u8, u8, bool),     //frD, frA, frB, Rc
+    Ps_merge11x(u8, u8, u8, bool),     //frD, frA, frB, Rc
+    Ps_sum0x(u8, u8, u8, u8, bool),    //frD, frA, frB, frC, Rc
+    Ps_sum1x(u8, u8, u8, u8, bool),    //frD, frA, frB, frC, Rc
+    Ps_selx(u8, u8, u8, u8, bool),     //frD, frA, frB, frC, Rc
+    Mfmsr(u8),                         //rD
+    Mtmsr(u8),                         //rS
+    Tw(u8, u8, u8),                    //TO, rA, rB
+    Twi(u8, u8, i16),                  //TO, rA, SIMM
+    Dcbf(u8, u8),                      //rA, rB
+    Dcbi(u8, u8),                      //rA, rB
+    Dcbst(u8, u8),                     //rA, rB
+    Dcbt(u8, u8),                      //rA, rB
+    Dcbtst(u8, u8),                    //rA, rB
+    Dcbz(u8, u8),                      //rA, rB
+    Icbi(u8, u8),                      //rA, rB
+    Dcbz_l(u8, u8),                    //rA, rB
+    Lswi(u8, u8, u8),                  //rD, rA, NB
+    Lswx(u8, u8, u8),                  //rD, rA, rB
+    Stswi(u8, u8, u8),                 //rS, rA, NB
+    Stswx(u8, u8, u8),                 //rS, rA, rB
+    Lwarx(u8, u8, u8),                 //rD, rA, rB
+    Stwcxdot(u8, u8, u8),              //rS, rA, rB
+    Mfsr(u8, u8),                      //rD, SR
+    Mfsrin(u8, u8),                    //rD, rB
+    Mtsr(u8, u8),                      //rS, SR
+    Mtsrin(u8, u8),                    //rS, rB
+    Rfi,
+    Sc,
+    Sync,
+    Isync,
+    Eieio,
     CustomBreak,
 }
 
 impl Instruction {
+    /// true for the instructions that raise a floating point unavailable exception when MSR[FP] is 0
+    pub fn use_floating_point(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Lfd(..)
+                | Instruction::Frsqrtex(..)
+                | Instruction::Fmulx(..)
+                | Instruction::Fnmsubx(..)
+                | Instruction::Frspx(..)
+                | Instruction::Stfs(..)
+                | Instruction::Lfs(..)
+                | Instruction::Stfdu(..)
+                | Instruction::Stfd(..)
+                | Instruction::Psq_st(..)
+                | Instruction::Psq_l(..)
+                | Instruction::Psq_stu(..)
+                | Instruction::Psq_lu(..)
+                | Instruction::Psq_stx(..)
+                | Instruction::Psq_stux(..)
+                | Instruction::Psq_lx(..)
+                | Instruction::Psq_lux(..)
+                | Instruction::Ps_cmpu0(..)
+                | Instruction::Ps_cmpo0(..)
+                | Instruction::Ps_cmpu1(..)
+                | Instruction::Ps_cmpo1(..)
+                | Instruction::Ps_negx(..)
+                | Instruction::Ps_mrx(..)
+                | Instruction::Ps_nabsx(..)
+                | Instruction::Ps_absx(..)
+                | Instruction::Ps_merge00x(..)
+                | Instruction::Ps_merge01x(..)
+                | Instruction::Ps_merge10x(..)
+                | Instruction::Ps_merge11x(..)
+                | Instruction::Ps_sum0x(..)
+                | Instruction::Ps_sum1x(..)
+                | Instruction::Ps_selx(..)
+        )
+    }
+
+    /// true for the instructions that raise a privileged program exception when MSR[PR] is 1
+    pub fn is_supervisor(&self) -> bool {
+        match self {
+            Instruction::Mfmsr(_)
+            | Instruction::Mtmsr(_)
+            | Instruction::Rfi
+            | Instruction::Dcbi(..)
+            | Instruction::Mfsr(..)
+            | Instruction::Mfsrin(..)
+            | Instruction::Mtsr(..)
+            | Instruction::Mtsrin(..) => true,
+            Instruction::Mfspr(_, spr) => spr.is_supervisor(),
+            Instruction::Mtspr(_, spr) => spr.is_supervisor(),
+            _ => false,
+        }
+    }
+
+    /// true for the instructions that end a basic block: the branches, the ones that change the
+    /// state the following instructions are fetched and run in, and the ones that depend on the
+    /// exact number of cycles run
+    pub fn ends_block(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Bx(..)
+                | Instruction::Bcx(..)
+                | Instruction::Bclrx(..)
+                | Instruction::Sc
+                | Instruction::Rfi
+                | Instruction::Tw(..)
+                | Instruction::Twi(..)
+                | Instruction::Mtmsr(_)
+                | Instruction::Mtspr(..)
+                | Instruction::Mfspr(..)
+                | Instruction::Mftb(..)
+                | Instruction::Mtsr(..)
+                | Instruction::Mtsrin(..)
+                | Instruction::Isync
+                | Instruction::Icbi(..)
+                | Instruction::CustomBreak
+        )
+    }
+
+    #[allow(clippy::bool_assert_comparison)]
     pub fn decode_instruction(opcode: u32) -> Option<Instruction> {
         let primary_opcode = opcode >> (31 - 5);
         Some(match primary_opcode {
+            3 => Instruction::Twi(
+                get_bit_section(opcode, 6, 5) as u8,
+                get_bit_section(opcode, 11, 5) as u8,
+                get_bit_section(opcode, 16, 16) as i16,
+            ),
+            4 => {
+                // the paired single instructions use either a 5 or a 10 bits extended opcode
+                let short_extended_opcode = get_bit_section(opcode, 26, 5);
+                match short_extended_opcode {
+                    6 | 7 => {
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        let fr = get_bit_section(opcode, 6, 5) as u8;
+                        let gpr_a = get_bit_section(opcode, 11, 5) as u8;
+                        let gpr_b = get_bit_section(opcode, 16, 5) as u8;
+                        let w = get_bit_value(opcode, 21);
+                        let i = get_bit_section(opcode, 22, 3) as u8;
+                        let update = get_bit_value(opcode, 25);
+                        match (short_extended_opcode, update) {
+                            (6, false) => Instruction::Psq_lx(fr, gpr_a, gpr_b, w, i),
+                            (6, true) => Instruction::Psq_lux(fr, gpr_a, gpr_b, w, i),
+                            (_, false) => Instruction::Psq_stx(fr, gpr_a, gpr_b, w, i),
+                            (_, true) => Instruction::Psq_stux(fr, gpr_a, gpr_b, w, i),
+                        }
+                    }
+                    10 => Instruction::Ps_sum0x(
+                        get_bit_section(opcode, 6, 5) as u8,
+                        get_bit_section(opcode, 11, 5) as u8,
+                        get_bit_section(opcode, 16, 5) as u8,
+                        get_bit_section(opcode, 21, 5) as u8,
+                        get_bit_value(opcode, 31),
+                    ),
+                    11 => Instruction::Ps_sum1x(
+                        get_bit_section(opcode, 6, 5) as u8,
+                        get_bit_section(opcode, 11, 5) as u8,
+                        get_bit_section(opcode, 16, 5) as u8,
+                        get_bit_section(opcode, 21, 5) as u8,
+                        get_bit_value(opcode, 31),
+                    ),
+                    23 => Instruction::Ps_selx(
+                        get_bit_section(opcode, 6, 5) as u8,
+                        get_bit_section(opcode, 11, 5) as u8,
+                        get_bit_section(opcode, 16, 5) as u8,
+                        get_bit_section(opcode, 21, 5) as u8,
+                        get_bit_value(opcode, 31),
+                    ),
+                    _ => {
+                        let extended_opcode = get_bit_section(opcode, 21, 10);
+                        match extended_opcode {
+                            0 | 32 | 64 | 96 => {
+                                debug_assert_eq!(get_bit_section(opcode, 9, 2), 0);
+                                debug_assert_eq!(get_bit_value(opcode, 31), false);
+                                let crf_d = get_bit_section(opcode, 6, 3) as u8;
+                                let fr_a = get_bit_section(opcode, 11, 5) as u8;
+                                let fr_b = get_bit_section(opcode, 16, 5) as u8;
+                                match extended_opcode {
+                                    0 => Instruction::Ps_cmpu0(crf_d, fr_a, fr_b),
+                                    32 => Instruction::Ps_cmpo0(crf_d, fr_a, fr_b),
+                                    64 => Instruction::Ps_cmpu1(crf_d, fr_a, fr_b),
+                                    _ => Instruction::Ps_cmpo1(crf_d, fr_a, fr_b),
+                                }
+                            }
+                            40 | 72 | 136 | 264 => {
+                                debug_assert_eq!(get_bit_section(opcode, 11, 5), 0);
+                                let fr_d = get_bit_section(opcode, 6, 5) as u8;
+                                let fr_b = get_bit_section(opcode, 16, 5) as u8;
+                                let rc = get_bit_value(opcode, 31);
+                                match extended_opcode {
+                                    40 => Instruction::Ps_negx(fr_d, fr_b, rc),
+                                    72 => Instruction::Ps_mrx(fr_d, fr_b, rc),
+                                    136 => Instruction::Ps_nabsx(fr_d, fr_b, rc),
+                                    _ => Instruction::Ps_absx(fr_d, fr_b, rc),
+                                }
+                            }
+                            528 | 560 | 592 | 624 => {
+                                let fr_d = get_bit_section(opcode, 6, 5) as u8;
+                                let fr_a = get_bit_section(opcode, 11, 5) as u8;
+                                let fr_b = get_bit_section(opcode, 16, 5) as u8;
+                                let rc = get_bit_value(opcode, 31);
+                                match extended_opcode {
+                                    528 => Instruction::Ps_merge00x(fr_d, fr_a, fr_b, rc),
+                                    560 => Instruction::Ps_merge01x(fr_d, fr_a, fr_b, rc),
+                                    592 => Instruction::Ps_merge10x(fr_d, fr_a, fr_b, rc),
+                                    _ => Instruction::Ps_merge11x(fr_d, fr_a, fr_b, rc),
+                                }
+                            }
+                            1014 => {
+                                debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                                Instruction::Dcbz_l(
+                                    get_bit_section(opcode, 11, 5) as u8,
+                                    get_bit_section(opcode, 16, 5) as u8,
+                                )
+                            }
+                            _ => return None,
+                        }
+                    }
+                }
+            }
             10 => {
                 debug_assert_eq!(get_bit_value(opcode, 9), false);
                 debug_assert_eq!(get_bit_value(opcode, 10), false);
@@ -100,6 +327,10 @@ impl Instruction {
                 get_bit_value(opcode, 30),
                 get_bit_value(opcode, 31),
             ),
+            17 => {
+                debug_assert_eq!(get_bit_value(opcode, 30), true);
+                Instruction::Sc
+            }
             18 => Instruction::Bx(
                 extend_sign_32(get_bit_section(opcode, 6, 24), 24),
                 get_bit_value(opcode, 30),
@@ -116,6 +347,12 @@ impl Instruction {
                             get_bit_value(opcode, 31),
                         )
                     }
+                    50 => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 15), 0);
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        Instruction::Rfi
+                    }
+                    150 => Instruction::Isync,
                     193 => {
                         debug_assert_eq!(get_bit_value(opcode, 31), false);
                         Instruction::Crxor(
@@ -167,6 +404,14 @@ impl Instruction {
                             get_bit_section(opcode, 16, 5) as u8,
                         )
                     }
+                    4 => {
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        Instruction::Tw(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
                     10 => Instruction::Addcx(
                         get_bit_section(opcode, 6, 5) as u8,
                         get_bit_section(opcode, 11, 5) as u8,
@@ -200,6 +445,60 @@ impl Instruction {
                         get_bit_value(opcode, 21),
                         get_bit_value(opcode, 31),
                     ),
+                    54 => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Dcbst(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    // mfsr is 595, and share its lower 9 bits with mfmsr
+                    83 if get_bit_value(opcode, 21) => {
+                        debug_assert_eq!(get_bit_value(opcode, 11), false);
+                        debug_assert_eq!(get_bit_section(opcode, 16, 5), 0);
+                        Instruction::Mfsr(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 12, 4) as u8,
+                        )
+                    }
+                    // mfsrin is 659
+                    147 if get_bit_value(opcode, 21) => {
+                        debug_assert_eq!(get_bit_section(opcode, 11, 5), 0);
+                        Instruction::Mfsrin(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    210 => {
+                        debug_assert_eq!(get_bit_value(opcode, 11), false);
+                        debug_assert_eq!(get_bit_section(opcode, 16, 5), 0);
+                        Instruction::Mtsr(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 12, 4) as u8,
+                        )
+                    }
+                    242 => {
+                        debug_assert_eq!(get_bit_section(opcode, 11, 5), 0);
+                        Instruction::Mtsrin(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    83 => {
+                        debug_assert_eq!(get_bit_section(opcode, 11, 10), 0);
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        Instruction::Mfmsr(get_bit_section(opcode, 6, 5) as u8)
+                    }
+                    // the 10 bits extended opcode of sync is 598, which share its lower 9 bits
+                    // with dcbf
+                    86 if get_bit_value(opcode, 21) => Instruction::Sync,
+                    86 => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Dcbf(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
                     124 => {
                         debug_assert_eq!(get_bit_value(opcode, 21), false);
                         Instruction::Nor(
@@ -216,6 +515,11 @@ impl Instruction {
                         get_bit_value(opcode, 21),
                         get_bit_value(opcode, 31),
                     ),
+                    146 => {
+                        debug_assert_eq!(get_bit_section(opcode, 11, 10), 0);
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        Instruction::Mtmsr(get_bit_section(opcode, 6, 5) as u8)
+                    }
                     151 => {
                         debug_assert_eq!(get_bit_value(opcode, 21), false);
                         debug_assert_eq!(get_bit_value(opcode, 31), false);
@@ -225,6 +529,13 @@ impl Instruction {
                             get_bit_section(opcode, 16, 5) as u8,
                         )
                     }
+                    246 => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Dcbtst(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
                     266 => Instruction::Addx(
                         get_bit_section(opcode, 6, 5) as u8,
                         get_bit_section(opcode, 11, 5) as u8,
@@ -232,19 +543,28 @@ impl Instruction {
                         get_bit_value(opcode, 21),
                         get_bit_value(opcode, 31),
                     ),
+                    278 => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Dcbt(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
                     339 => {
                         debug_assert_eq!(get_bit_value(opcode, 31), false);
                         Instruction::Mfspr(
                             get_bit_section(opcode, 6, 5) as u8,
-                            Spr::decode_from_mfspr(get_bit_section(opcode, 11, 10) as u16),
+                            Spr::decode_from_mfspr(get_bit_section(opcode, 11, 10) as u16)?,
                         )
                     }
+                    // eieio is 854
+                    342 if get_bit_value(opcode, 21) => Instruction::Eieio,
                     371 => {
                         debug_assert_eq!(get_bit_value(opcode, 31), false);
                         debug_assert_eq!(get_bit_value(opcode, 21), false);
                         Instruction::Mftb(
                             get_bit_section(opcode, 6, 5) as u8,
-                            Tbr::decode_from_mftb(get_bit_section(opcode, 11, 10) as u16),
+                            Tbr::decode_from_mftb(get_bit_section(opcode, 11, 10) as u16)?,
                         )
                     }
                     442 => {
@@ -268,7 +588,60 @@ impl Instruction {
                         debug_assert_eq!(get_bit_value(opcode, 31), false);
                         Instruction::Mtspr(
                             get_bit_section(opcode, 6, 5) as u8,
-                            Spr::decode_from_mfspr(get_bit_section(opcode, 11, 10) as u16),
+                            Spr::decode_from_mtspr(get_bit_section(opcode, 11, 10) as u16)?,
+                        )
+                    }
+                    20 => {
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        Instruction::Lwarx(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    // stwcx. share its lower 9 bits with stwbrx (662)
+                    150 if !get_bit_value(opcode, 21) => {
+                        debug_assert_eq!(get_bit_value(opcode, 31), true);
+                        Instruction::Stwcxdot(
+                            get_bit_section(opcode, 6, 5) as u8,
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    // the string instructions are lswx 533, lswi 597, stswx 661 and stswi 725
+                    21 | 85 | 149 | 213 if get_bit_value(opcode, 21) => {
+                        debug_assert_eq!(get_bit_value(opcode, 31), false);
+                        let gpr = get_bit_section(opcode, 6, 5) as u8;
+                        let gpr_a = get_bit_section(opcode, 11, 5) as u8;
+                        let field_b = get_bit_section(opcode, 16, 5) as u8;
+                        match extended_opcode {
+                            21 => Instruction::Lswx(gpr, gpr_a, field_b),
+                            85 => Instruction::Lswi(gpr, gpr_a, field_b),
+                            149 => Instruction::Stswx(gpr, gpr_a, field_b),
+                            _ => Instruction::Stswi(gpr, gpr_a, field_b),
+                        }
+                    }
+                    // icbi is 982, and share its lower 9 bits with dcbi
+                    470 if get_bit_value(opcode, 21) => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Icbi(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    470 => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Dcbi(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
+                        )
+                    }
+                    // dcbz is 1014
+                    502 if get_bit_value(opcode, 21) => {
+                        debug_assert_eq!(get_bit_section(opcode, 6, 5), 0);
+                        Instruction::Dcbz(
+                            get_bit_section(opcode, 11, 5) as u8,
+                            get_bit_section(opcode, 16, 5) as u8,
                         )
                     }
                     _ => return None,
@@ -361,6 +734,13 @@ impl Instruction {
                 get_bit_section(opcode, 17, 3) as u8,
                 extend_sign_32(get_bit_section(opcode, 20, 12), 12) as i16,
             ),
+            57 => Instruction::Psq_lu(
+                get_bit_section(opcode, 6, 5) as u8,
+                get_bit_section(opcode, 11, 5) as u8,
+                get_bit_value(opcode, 16),
+                get_bit_section(opcode, 17, 3) as u8,
+                extend_sign_32(get_bit_section(opcode, 20, 12), 12) as i16,
+            ),
             59 => {
                 let extended_opcode = get_bit_section(opcode, 26, 5);
                 match extended_opcode {
@@ -376,6 +756,13 @@ impl Instruction {
                 get_bit_section(opcode, 17, 3) as u8,
                 extend_sign_32(get_bit_section(opcode, 20, 12), 12) as i16,
             ),
+            61 => Instruction::Psq_stu(
+                get_bit_section(opcode, 6, 5) as u8,
+                get_bit_section(opcode, 11, 5) as u8,
+                get_bit_value(opcode, 16),
+                get_bit_section(opcode, 17, 3) as u8,
+                extend_sign_32(get_bit_section(opcode, 20, 12), 12) as i16,
+            ),
             63 => {
                 let upper_extended_opcode = get_bit_section(opcode, 26, 5);
                 match upper_extended_opcode {
@@ -421,9 +808,58 @@ impl Instruction {
 }
 
 #[test]
+#[allow(clippy::unusual_byte_groupings)]
 fn test_decode() {
     assert_eq!(
         Instruction::decode_instruction(0b011111_00010_00011_00100_0_100001010_1),
         Some(Instruction::Addx(2, 3, 4, false, true))
     );
+    assert_eq!(
+        Instruction::decode_instruction(0b000100_00001_00010_00011_00100_01010_0),
+        Some(Instruction::Ps_sum0x(1, 2, 3, 4, false))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b000100_01100_00010_00011_0001000000_0),
+        Some(Instruction::Ps_cmpu1(3, 2, 3))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b000100_00001_00000_00011_0001001000_1),
+        Some(Instruction::Ps_mrx(1, 3, true))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b000100_00001_00010_00011_1_101_100111_0),
+        Some(Instruction::Psq_stux(1, 2, 3, true, 5))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0x7C0004AC),
+        Some(Instruction::Sync)
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b011111_00000_00011_00100_0001010110_0),
+        Some(Instruction::Dcbf(3, 4))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b011111_00000_00011_00100_1111010110_0),
+        Some(Instruction::Icbi(3, 4))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b000100_00000_00011_00100_1111110110_0),
+        Some(Instruction::Dcbz_l(3, 4))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b011111_00011_0_1110_00000_1001010011_0),
+        Some(Instruction::Mfsr(3, 14))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b011111_00011_00000_00100_0011110010_0),
+        Some(Instruction::Mtsrin(3, 4))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b011111_00101_00011_00000_1001010101_0),
+        Some(Instruction::Lswi(5, 3, 0))
+    );
+    assert_eq!(
+        Instruction::decode_instruction(0b011111_00101_00011_00100_1010010101_0),
+        Some(Instruction::Stswx(5, 3, 4))
+    );
 }
@@ -0,0 +1,74 @@
+use crate::memory::{for_each_page, page_number, page_offset};
+use crate::{Instruction, PAGE_SIZE};
+
+const INSTRUCTIONS_PER_PAGE: usize = PAGE_SIZE / 4;
+
+type DecodedPage = Box<[Option<Instruction>; INSTRUCTIONS_PER_PAGE]>;
+
+/// The instructions already decoded, by physical address, so that step doesn't have to fetch
+/// and decode them again. It is kept in sync with the memory by invalidating the instructions
+/// overwritten by every write the interpreter see.
+pub(crate) struct InstructionCache {
+    // by physical page number, up to the last page with code. The pages without code are never
+    // allocated.
+    pages: Vec<Option<DecodedPage>>,
+}
+
+impl InstructionCache {
+    pub fn new() -> InstructionCache {
+        InstructionCache { pages: Vec::new() }
+    }
+
+    #[inline]
+    pub fn get(&self, physical: u32) -> Option<Instruction> {
+        self.pages.get(page_number(physical) as usize)?.as_ref()?[instruction_index(physical)]
+    }
+
+    pub fn insert(&mut self, physical: u32, instruction: Instruction) {
+        let page = page_number(physical) as usize;
+        if page >= self.pages.len() {
+            self.pages.resize_with(page + 1, || None);
+        };
+        self.pages[page].get_or_insert_with(|| Box::new([None; INSTRUCTIONS_PER_PAGE]))
+            [instruction_index(physical)] = Some(instruction);
+    }
+
+    /// forget the instructions overlapping the size bytes at physical
+    #[inline]
+    pub fn invalidate(&mut self, physical: u32, size: u32) {
+        for_each_page(physical, size as usize, |address, range| {
+            if let Some(Some(decoded)) = self.pages.get_mut(page_number(address) as usize) {
+                let last_byte = address + range.len() as u32 - 1;
+                decoded[instruction_index(address)..=instruction_index(last_byte)].fill(None);
+            };
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+#[inline]
+fn instruction_index(physical: u32) -> usize {
+    page_offset(physical) >> 2
+}
+
+#[test]
+fn test_instruction_cache() {
+    let mut cache = InstructionCache::new();
+    cache.insert(0x1000, Instruction::Isync);
+    cache.insert(0x1FFC, Instruction::Sync);
+    cache.insert(0x2000, Instruction::Eieio);
+    assert_eq!(cache.get(0x1000), Some(Instruction::Isync));
+    assert_eq!(cache.get(0x1004), None);
+    cache.invalidate(0x1003, 1);
+    assert_eq!(cache.get(0x1000), None);
+    // across two pages
+    cache.invalidate(0x1FFE, 4);
+    assert_eq!(cache.get(0x1FFC), None);
+    assert_eq!(cache.get(0x2000), None);
+    cache.insert(0x3000, Instruction::Isync);
+    cache.clear();
+    assert_eq!(cache.get(0x3000), None);
+}
@@ -1,63 +1,435 @@
+use crate::block::BlockCache;
+use crate::instruction_cache::InstructionCache;
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
+use crate::mmu::{BatTables, TranslationFault};
+use crate::register::{
+    FPSCR_VE, FPSCR_VXSNAN, FPSCR_VXVC, HID2_LCE, MSR_EE, MSR_FP, MSR_ILE, MSR_IP, MSR_LE, MSR_ME,
+    MSR_POW, MSR_PR, MSR_RFI_MASK,
+};
 use crate::util::{
-    get_bit_section, get_size_for_quantized_type, make_rotation_mask, raw_u64_to_f64, u8_get_bit,
+    get_bit_section, get_quantization_scale, get_size_for_quantized_type, is_signaling_nan,
+    make_rotation_mask, raw_u64_to_f64, u8_get_bit,
 };
+use crate::AccessKind;
 use crate::GekkoRegister;
 use crate::Instruction;
-use crate::Tbr;
-use crate::BASE_RW_ADRESS;
+use crate::TimeBase;
+use crate::{EventCallback, EventId, Scheduler};
+use crate::{Exception, ExceptionKind, ProgramReason};
+use crate::{GuestHeap, HostData};
+use crate::{GuestMemory, MemoryMap};
+use crate::{MmioAccess, MmioBus, MmioDevice};
+use crate::{Spr, Tbr};
+use crate::{LOCKED_CACHE_ADRESS, LOCKED_CACHE_SIZE};
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::mem::replace;
 
+/// size of a block of the L1 caches, in bytes
+pub const CACHE_BLOCK_SIZE: u32 = 32;
+
+// bits of DMA_L
+const DMA_L_LD: u32 = 0x0000_0010;
+const DMA_L_T: u32 = 0x0000_0002;
+const DMA_L_F: u32 = 0x0000_0001;
+
 #[derive(Debug, PartialEq)]
 pub enum BreakData {
     None,
     Break,
+    // an exception whose kind is in GekkoInterpreter::break_on_exception. The state of the CPU
+    // is left as it was before the instruction that caused it.
+    Exception(Exception),
+    // a sc instruction, when GekkoInterpreter::break_on_system_call is set. pc point to the
+    // instruction that follow it.
+    SystemCall,
+    // a trap instruction whose condition was met, when GekkoInterpreter::break_on_trap is set.
+    // pc is the address of the trap instruction, and the execution will resume after it.
+    Trap { pc: u32, kind: TrapKind },
+    // an access to a hardware register that no device handled, when
+    // GekkoInterpreter::break_on_unhandled_mmio is set. The instruction has been executed.
+    UnhandledMmio(MmioAccess),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TrapKind {
+    Tw { to: u8, gpr_a: u8, gpr_b: u8 },
+    Twi { to: u8, gpr_a: u8, simm: i16 },
 }
 
-pub struct GekkoInterpreter {
-    pub ram: Vec<u8>,
+/// the logging is enabled when this instruction is reached, to debug the code that follow it
+pub(crate) const LOG_TRIGGER_ADDRESS: u32 = 0x80003264;
+
+// where an effective address lead to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Location {
+    Physical(u32),
+    LockedCache(usize),
+}
+
+/// The interpreter of the Gekko, running on the physical memory M. It is a MemoryMap by
+/// default, but it may be anything implementing GuestMemory, like a SparseMemory for tests.
+pub struct GekkoInterpreter<M = MemoryMap> {
+    pub memory: M,
+    // the locked half of the L1 data cache, see LOCKED_CACHE_ADRESS
+    pub locked_cache: Vec<u8>,
     pub register: GekkoRegister,
     pub counter: u64,
     pub log: bool,
+    // the kinds of exception that are returned by step instead of jumping to their handler
+    pub break_on_exception: HashSet<ExceptionKind>,
+    // return BreakData::SystemCall for sc, instead of taking the system call exception
+    pub break_on_system_call: bool,
+    // return BreakData::Trap for tw and twi, instead of taking a program exception
+    pub break_on_trap: bool,
+    // return BreakData::UnhandledMmio for the accesses to hardware registers no device handled
+    pub break_on_unhandled_mmio: bool,
+    // run_until_event run whole basic blocks instead of single steps. Disabled when logging.
+    pub use_blocks: bool,
+    // compare each run of compiled code with the interpreter, and panic if they differ. Only
+    // when the JIT is enabled, see enable_jit. The accesses to the hardware registers of the
+    // blocks are done twice.
+    #[cfg(feature = "jit")]
+    pub jit_lockstep: bool,
+    // level of the external interrupt line
+    pub external_interrupt: bool,
+    // the decrementer went below 0, and the interrupt hasn't been taken yet
+    pub decrementer_pending: bool,
+    pub time: TimeBase,
+    pub scheduler: Scheduler<M>,
+    // the physical address of the cache block reserved by lwarx, until a write to it, an
+    // exception or a stwcx.
+    pub reservation: Option<u32>,
+    // the Rust values attached to guest objects by the HLE functions
+    pub host_data: HostData,
+    // the heaps created by the HLE functions, by handle. None once destroyed.
+    pub heaps: Vec<Option<GuestHeap>>,
+    bat_tables: BatTables,
+    instruction_cache: InstructionCache,
+    pub(crate) block_cache: BlockCache,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: Option<Jit>,
+    // the previous content of the memory written, while the compiled code is compared with the
+    // interpreter, to undo the writes of the first run
+    #[cfg(feature = "jit")]
+    pub(crate) journal: Option<Vec<(Location, Vec<u8>)>>,
+    // the reads of the hardware registers may change the state of the devices
+    mmio: RefCell<MmioBus>,
+}
+
+/// check the condition of tw and twi. Each bit of to enable a comparison, from the highest to
+/// the lowest: signed lesser, signed greater, equal, unsigned lesser and unsigned greater.
+fn check_trap_condition(to: u8, a: u32, b: u32) -> bool {
+    let (signed_a, signed_b) = (a as i32, b as i32);
+    (u8_get_bit(to, 3) && signed_a < signed_b)
+        || (u8_get_bit(to, 4) && signed_a > signed_b)
+        || (u8_get_bit(to, 5) && a == b)
+        || (u8_get_bit(to, 6) && a < b)
+        || (u8_get_bit(to, 7) && a > b)
 }
 
 impl GekkoInterpreter {
+    /// an interpreter with only ram_amount bytes of MEM1, instead of the 24 MiB of a GameCube
     pub fn new(ram_amount: usize) -> GekkoInterpreter {
+        GekkoInterpreter::with_memory(MemoryMap::new(ram_amount))
+    }
+}
+
+impl<M: GuestMemory> GekkoInterpreter<M> {
+    pub fn with_memory(memory: M) -> GekkoInterpreter<M> {
         GekkoInterpreter {
-            ram: vec![0; ram_amount],
+            memory,
+            locked_cache: vec![0; LOCKED_CACHE_SIZE],
             register: GekkoRegister::default(),
             counter: 0,
             log: false,
+            break_on_exception: HashSet::new(),
+            break_on_system_call: false,
+            break_on_trap: false,
+            break_on_unhandled_mmio: false,
+            use_blocks: true,
+            #[cfg(feature = "jit")]
+            jit_lockstep: false,
+            external_interrupt: false,
+            decrementer_pending: false,
+            time: TimeBase::default(),
+            scheduler: Scheduler::default(),
+            reservation: None,
+            host_data: HostData::default(),
+            heaps: Vec::new(),
+            bat_tables: BatTables::new(&GekkoRegister::default()),
+            instruction_cache: InstructionCache::new(),
+            block_cache: BlockCache::new(),
+            #[cfg(feature = "jit")]
+            jit: None,
+            #[cfg(feature = "jit")]
+            journal: None,
+            mmio: RefCell::new(MmioBus::default()),
         }
     }
 
     pub fn get_timebase(&self) -> u64 {
-        self.counter >> 3
+        self.time.get_timebase(self.counter)
+    }
+
+    /// get a special purpose register, including the ones that depend on the time, like the
+    /// decrementer
+    pub fn get_spr(&self, spr: Spr) -> u32 {
+        match spr {
+            Spr::DEC => self.time.get_decrementer(self.counter),
+            Spr::TBL => self.get_timebase() as u32,
+            Spr::TBU => (self.get_timebase() >> 32) as u32,
+            spr => self.register.get_spr(spr),
+        }
+    }
+
+    /// set a special purpose register, including the ones that depend on the time, like the
+    /// decrementer. Like mtspr, this update the BAT translation and start the locked cache DMA,
+    /// and the read only registers raise an illegal instruction program exception.
+    pub fn set_spr(&mut self, spr: Spr, value: u32) -> Result<(), Exception> {
+        match spr {
+            spr if spr.is_read_only() => {
+                return Err(Exception::Program(ProgramReason::IllegalInstruction))
+            }
+            Spr::DEC => {
+                if self.time.set_decrementer(self.counter, value) {
+                    self.decrementer_pending = true;
+                }
+            }
+            Spr::TBL => self.time.set_tbl(self.counter, value),
+            Spr::TBU => self.time.set_tbu(self.counter, value),
+            Spr::IBATU(_) | Spr::IBATL(_) | Spr::DBATU(_) | Spr::DBATL(_) => {
+                self.register.set_spr(spr, value);
+                self.update_bat_tables();
+            }
+            Spr::DMA_L => {
+                self.register.dma_l = value;
+                if value & DMA_L_T != 0 {
+                    self.run_locked_cache_dma()?;
+                }
+            }
+            spr => self.register.set_spr(spr, value),
+        }
+        Ok(())
+    }
+
+    /// take into account a change of the BAT registers. It is done by mtspr, but should be called
+    /// after writing them directly in GekkoInterpreter::register.
+    pub fn update_bat_tables(&mut self) {
+        self.bat_tables.update(&self.register);
+    }
+
+    /// perform the transfer described by DMA_U and DMA_L between the locked cache and the main
+    /// memory. It complete immediately, so the trigger and flush bits are cleared right away.
+    /// An access outside of the memory is reported as a machine check.
+    fn run_locked_cache_dma(&mut self) -> Result<(), Exception> {
+        let dma_u = self.register.dma_u;
+        let dma_l = self.register.dma_l;
+        self.register.dma_l &= !(DMA_L_T | DMA_L_F);
+        if self.register.hid2 & HID2_LCE == 0 {
+            return Ok(());
+        }
+        // DMA_U hold a physical address, and DMA_L an address in the locked cache
+        let memory_address = dma_u & !0x1F;
+        let cache_offset = (dma_l & !0x1F).wrapping_sub(LOCKED_CACHE_ADRESS) as usize;
+        // the length is in cache blocks, 0 meaning 128
+        let length = match ((dma_u & 0x1F) << 2) | ((dma_l >> 2) & 0x3) {
+            0 => 128,
+            count => count,
+        } as usize
+            * CACHE_BLOCK_SIZE as usize;
+        #[cfg(feature = "jit")]
+        if dma_l & DMA_L_LD != 0 {
+            self.journal_write(Location::LockedCache(cache_offset), length);
+        } else {
+            self.journal_write(Location::Physical(memory_address), length);
+        };
+        let cache = self
+            .locked_cache
+            .get_mut(cache_offset..cache_offset + length)
+            .ok_or(Exception::MachineCheck)?;
+        if dma_l & DMA_L_LD != 0 {
+            self.memory
+                .read_bytes(memory_address, cache)
+                .map_err(|_| Exception::MachineCheck)?;
+        } else {
+            self.memory
+                .write_bytes(memory_address, cache)
+                .map_err(|_| Exception::MachineCheck)?;
+            self.clear_reservation(memory_address, length as u32);
+            self.invalidate_code(memory_address, length as u32);
+        };
+        Ok(())
     }
 
-    pub fn replace_memory(&mut self, new_ram: Vec<u8>) -> Vec<u8> {
-        replace(&mut self.ram, new_ram)
+    /// swap the physical memory, returning the previous one
+    pub fn replace_memory(&mut self, new_memory: M) -> M {
+        self.flush_instruction_cache();
+        replace(&mut self.memory, new_memory)
     }
 
     pub fn reboot(&mut self) {
-        self.ram = vec![0; self.ram.len()];
+        self.memory.clear();
+        self.locked_cache.fill(0);
         self.register = GekkoRegister::default();
+        self.external_interrupt = false;
+        self.decrementer_pending = false;
+        self.time = TimeBase::default();
+        self.scheduler = Scheduler::default();
+        self.reservation = None;
+        self.host_data.clear();
+        self.heaps.clear();
+        self.bat_tables.update(&self.register);
+        self.flush_instruction_cache();
+        self.counter = 0;
     }
 
     pub fn step(&mut self) -> Result<BreakData, String> {
         self.counter += 1;
+        if self.time.check_decrementer(self.counter) {
+            self.decrementer_pending = true;
+        };
+        // asynchronous interrupts are taken between two instructions
+        if let Some(interrupt) = self.get_pending_interrupt() {
+            return Ok(self.take_exception(interrupt));
+        };
         // first, get the instruction
-        if self.register.pc == 0x80003264 {
+        if self.register.pc == LOG_TRIGGER_ADDRESS {
             self.log = true;
         }
+        let instruction = match self.fetch_instruction(self.register.pc) {
+            Ok(instruction) => instruction,
+            Err(exception) => return Ok(self.take_exception(exception)),
+        };
+        if self.log {
+            println!("{:?}", instruction);
+        }
+        // second, run it
+        let break_data = match self.execute(instruction) {
+            Ok(break_data) => break_data,
+            Err(exception) => self.take_exception(exception),
+        };
+        Ok(self.report_unhandled_mmio(break_data))
+    }
+
+    /// fetch and decode the instruction at address, or get it from the instruction cache. Return
+    /// an ISI if it can't be fetched, and a program exception if it is invalid.
+    #[inline]
+    pub(crate) fn fetch_instruction(&mut self, address: u32) -> Result<Instruction, Exception> {
+        let physical = match self.locate(address, AccessKind::Fetch) {
+            Ok(Location::Physical(physical)) => physical,
+            _ => return Err(Exception::Isi { address }),
+        };
+        if !self.log {
+            if let Some(instruction) = self.instruction_cache.get(physical) {
+                return Ok(instruction);
+            };
+        };
+        let opcode = self.try_fetch(address)?;
         if self.log {
             println!("----");
-            println!("pc: 0x{:x}", self.register.pc);
-            println!("opcode: 0x{:x}", self.read_u32(self.register.pc));
+            println!("pc: 0x{:x}", address);
+            println!("opcode: 0x{:x}", opcode);
+        };
+        let instruction = Instruction::decode_instruction(opcode)
+            .ok_or(Exception::Program(ProgramReason::IllegalInstruction))?;
+        self.instruction_cache.insert(physical, instruction);
+        Ok(instruction)
+    }
+
+    /// log the accesses to hardware registers that no device handled, and report the first one
+    /// if break_on_unhandled_mmio is set and there is nothing else to report
+    #[inline]
+    pub(crate) fn report_unhandled_mmio(&mut self, break_data: BreakData) -> BreakData {
+        if !self.mmio.get_mut().has_unhandled() {
+            return break_data;
         };
-        let instruction = Instruction::decode_instruction(self.read_u32(self.register.pc)).unwrap();
+        let unhandled = self.mmio.get_mut().take_unhandled();
         if self.log {
-            println!("{:?}", instruction);
+            for access in &unhandled {
+                println!("unhandled mmio access: {:x?}", access);
+            }
+        };
+        match unhandled.first() {
+            Some(access) if self.break_on_unhandled_mmio && break_data == BreakData::None => {
+                BreakData::UnhandledMmio(*access)
+            }
+            _ => break_data,
+        }
+    }
+
+    /// route the accesses to the size bytes of physical memory starting at start to device
+    pub fn register_mmio_device(&mut self, start: u32, size: u32, device: Box<dyn MmioDevice>) {
+        self.mmio.get_mut().register(start, size, device);
+    }
+
+    /// check for an enabled asynchronous interrupt (external or decrementer)
+    pub(crate) fn get_pending_interrupt(&self) -> Option<Exception> {
+        if !self.register.get_msr_bit(MSR_EE) {
+            None
+        } else if self.external_interrupt {
+            Some(Exception::External)
+        } else if self.decrementer_pending {
+            Some(Exception::Decrementer)
+        } else {
+            None
         }
+    }
+
+    /// take an exception: save the state to SRR0/SRR1, update the MSR and jump to its vector.
+    /// If the kind of exception is in break_on_exception, the state is left untouched and the
+    /// exception is returned to the embedder instead.
+    pub fn take_exception(&mut self, exception: Exception) -> BreakData {
+        if self.break_on_exception.contains(&exception.kind()) {
+            return BreakData::Exception(exception);
+        };
+        self.reservation = None;
+        match exception {
+            Exception::Dsi {
+                address,
+                store,
+                protection,
+            } => {
+                self.register.dar = address;
+                // the address couldn't be translated or the access is forbidden, and if it was a
+                // store
+                self.register.dsisr = if protection { 0x0800_0000 } else { 0x4000_0000 }
+                    | if store { 0x0200_0000 } else { 0 };
+            }
+            Exception::Alignment { address, dsisr } => {
+                self.register.dar = address;
+                self.register.dsisr = dsisr;
+            }
+            Exception::Decrementer => self.decrementer_pending = false,
+            _ => (),
+        };
+        self.register.srr0 = if exception.resume_after_instruction() {
+            self.register.pc.wrapping_add(4)
+        } else {
+            self.register.pc
+        };
+        self.register.srr1 = (self.register.msr & MSR_RFI_MASK) | exception.srr1_flags();
+        let little_endian = self.register.get_msr_bit(MSR_ILE);
+        self.register.msr &= MSR_ILE | MSR_ME | MSR_IP;
+        self.register.set_msr_bit(MSR_LE, little_endian);
+        // the handlers are run without translation
+        let prefix = if self.register.get_msr_bit(MSR_IP) {
+            0xFFF0_0000
+        } else {
+            0
+        };
+        self.register.pc = prefix | exception.vector();
+        BreakData::None
+    }
+
+    pub(crate) fn execute(&mut self, instruction: Instruction) -> Result<BreakData, Exception> {
+        if instruction.use_floating_point() && !self.register.get_msr_bit(MSR_FP) {
+            return Err(Exception::FloatingPointUnavailable);
+        };
+        if instruction.is_supervisor() && self.register.get_msr_bit(MSR_PR) {
+            return Err(Exception::Program(ProgramReason::PrivilegedInstruction));
+        };
         // second, run it
         let mut break_data = BreakData::None;
         match instruction {
@@ -77,12 +449,12 @@ impl GekkoInterpreter {
             }
             Instruction::Stwu(gpr_s, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                self.write_u32(address, self.register.get_gpr(gpr_s));
+                self.try_write_u32(address, self.register.get_gpr(gpr_s))?;
                 self.register.set_gpr(gpr_a, address);
                 self.register.increment_pc();
             }
             Instruction::Mfspr(gpr_d, spr) => {
-                self.register.set_gpr(gpr_d, self.register.get_spr(spr));
+                self.register.set_gpr(gpr_d, self.get_spr(spr));
                 self.register.increment_pc();
             }
             Instruction::Cmpli(crf_d, gpr_a, uimm) => {
@@ -150,14 +522,17 @@ impl GekkoInterpreter {
             }
             Instruction::Stw(gpr_s, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                self.write_u32(address, self.register.get_gpr(gpr_s));
+                self.try_write_u32(address, self.register.get_gpr(gpr_s))?;
 
                 self.register.increment_pc();
             }
             Instruction::Stmw(mut gpr_s, gpr_a, d) => {
                 let mut address = self.register.compute_address_based_on_register(gpr_a, d);
+                if address & 3 != 0 {
+                    return Err(Exception::alignment(address, 47, None, gpr_s, gpr_a));
+                };
                 while gpr_s < 32 {
-                    self.write_u32(address, self.register.get_gpr(gpr_s));
+                    self.try_write_u32(address, self.register.get_gpr(gpr_s))?;
                     gpr_s += 1;
                     address += 4;
                 }
@@ -235,25 +610,25 @@ impl GekkoInterpreter {
             }
             Instruction::Lwz(gpr_d, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let new_value = self.read_u32(address);
+                let new_value = self.try_read_u32(address)?;
                 self.register.set_gpr(gpr_d, new_value);
                 self.register.increment_pc();
             }
             Instruction::Lwzu(gpr_d, gpr_a, d) => {
                 let address = (self.register.get_gpr(gpr_a) as i64 + (d as i64)) as u32;
-                let new_value = self.read_u32(address);
+                let new_value = self.try_read_u32(address)?;
                 self.register.set_gpr(gpr_d, new_value);
                 self.register.set_gpr(gpr_a, address);
                 self.register.increment_pc();
             }
             Instruction::Stb(gpr_s, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                self.write_u8(address, self.register.get_gpr(gpr_s) as u8);
+                self.try_write_u8(address, self.register.get_gpr(gpr_s) as u8)?;
                 self.register.increment_pc();
             }
             Instruction::Stbu(gpr_s, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                self.write_u8(address, self.register.get_gpr(gpr_s) as u8);
+                self.try_write_u8(address, self.register.get_gpr(gpr_s) as u8)?;
                 self.register.set_gpr(gpr_a, address);
                 self.register.increment_pc();
             }
@@ -318,14 +693,14 @@ impl GekkoInterpreter {
             Instruction::Lbz(gpr_d, gpr_a, d) => {
                 //TODO: some unit test for it
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let new_value = self.read_u8(address) as u32;
+                let new_value = self.try_read_u8(address)? as u32;
                 self.register.set_gpr(gpr_d, new_value);
                 self.register.increment_pc();
             }
             Instruction::Lbzu(gpr_d, gpr_a, d) => {
                 //TODO: some unit test for it
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let new_value = self.read_u8(address) as u32;
+                let new_value = self.try_read_u8(address)? as u32;
                 self.register.set_gpr(gpr_d, new_value);
                 self.register.set_gpr(gpr_a, address);
                 self.register.increment_pc();
@@ -345,7 +720,7 @@ impl GekkoInterpreter {
                     .register
                     .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
 
-                let value = self.read_u32(address);
+                let value = self.try_read_u32(address)?;
                 self.register.set_gpr(gpr_d, value);
                 self.register.increment_pc();
             }
@@ -353,22 +728,100 @@ impl GekkoInterpreter {
                 let address = self
                     .register
                     .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
-                self.write_u32(address, self.register.get_gpr(gpr_s));
+                self.try_write_u32(address, self.register.get_gpr(gpr_s))?;
                 self.register.increment_pc();
             }
             Instruction::Lmw(gpr_d, gpr_a, d) => {
                 let mut address = self.register.compute_address_based_on_register(gpr_a, d);
+                if address & 3 != 0 {
+                    return Err(Exception::alignment(address, 46, None, gpr_d, gpr_a));
+                };
                 let mut r = self.register.get_gpr(gpr_d);
                 while r < 32 {
-                    let value = self.read_u32(address);
+                    let value = self.try_read_u32(address)?;
                     self.register.set_gpr(r as u8, value);
                     r += 1;
                     address += 4;
                 }
                 self.register.increment_pc();
             }
+            Instruction::Mfsr(gpr_d, sr) => {
+                self.register.set_gpr(gpr_d, self.register.sr[sr as usize]);
+                self.register.increment_pc();
+            }
+            Instruction::Mfsrin(gpr_d, gpr_b) => {
+                let sr = self.register.get_gpr(gpr_b) >> 28;
+                self.register.set_gpr(gpr_d, self.register.sr[sr as usize]);
+                self.register.increment_pc();
+            }
+            Instruction::Mtsr(gpr_s, sr) => {
+                self.register.sr[sr as usize] = self.register.get_gpr(gpr_s);
+                self.register.increment_pc();
+            }
+            Instruction::Mtsrin(gpr_s, gpr_b) => {
+                let sr = self.register.get_gpr(gpr_b) >> 28;
+                self.register.sr[sr as usize] = self.register.get_gpr(gpr_s);
+                self.register.increment_pc();
+            }
+            Instruction::Lwarx(gpr_d, gpr_a, gpr_b) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                if address & 3 != 0 {
+                    return Err(Exception::alignment(address, 31, Some(20), gpr_d, gpr_a));
+                };
+                let value = self.try_read_u32(address)?;
+                self.register.set_gpr(gpr_d, value);
+                let physical = self.get_reservation_address(address, AccessKind::Load)?;
+                self.reservation = Some(physical & !(CACHE_BLOCK_SIZE - 1));
+                self.register.increment_pc();
+            }
+            Instruction::Stwcxdot(gpr_s, gpr_a, gpr_b) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                if address & 3 != 0 {
+                    return Err(Exception::alignment(address, 31, Some(150), gpr_s, gpr_a));
+                };
+                let physical = self.get_reservation_address(address, AccessKind::Store)?;
+                let stored = self.reservation == Some(physical & !(CACHE_BLOCK_SIZE - 1));
+                if stored {
+                    self.try_write_u32(address, self.register.get_gpr(gpr_s))?;
+                };
+                self.reservation = None;
+                self.register.cr[0] = ((stored as u8) << 1) | (self.register.get_xer_so() as u8);
+                self.register.increment_pc();
+            }
+            Instruction::Lswi(gpr_d, gpr_a, nb) => {
+                let address = self.register.compute_address_based_on_register(gpr_a, 0);
+                let count = if nb == 0 { 32 } else { nb as u32 };
+                self.load_string(gpr_d, address, count)?;
+                self.register.increment_pc();
+            }
+            Instruction::Lswx(gpr_d, gpr_a, gpr_b) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                let count = self.register.get_xer_byte_count() as u32;
+                self.load_string(gpr_d, address, count)?;
+                self.register.increment_pc();
+            }
+            Instruction::Stswi(gpr_s, gpr_a, nb) => {
+                let address = self.register.compute_address_based_on_register(gpr_a, 0);
+                let count = if nb == 0 { 32 } else { nb as u32 };
+                self.store_string(gpr_s, address, count)?;
+                self.register.increment_pc();
+            }
+            Instruction::Stswx(gpr_s, gpr_a, gpr_b) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                let count = self.register.get_xer_byte_count() as u32;
+                self.store_string(gpr_s, address, count)?;
+                self.register.increment_pc();
+            }
             Instruction::Mtspr(gpr_s, spr) => {
-                self.register.set_spr(spr, self.register.get_gpr(gpr_s));
+                self.set_spr(spr, self.register.get_gpr(gpr_s))?;
                 self.register.increment_pc();
             }
             Instruction::Ori(gpr_s, gpr_a, uuim) => {
@@ -398,15 +851,15 @@ impl GekkoInterpreter {
                 self.register.set_gpr(
                     gpr_d,
                     match tbr {
-                        Tbr::Tbl => (self.get_timebase() >> 32) as u32,
-                        Tbr::Tbu => (self.get_timebase() << 32 >> 32) as u32,
+                        Tbr::Tbl => self.get_timebase() as u32,
+                        Tbr::Tbu => (self.get_timebase() >> 32) as u32,
                     },
                 );
                 self.register.increment_pc();
             }
             Instruction::Lhz(gpr_d, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let value = self.read_u16(address) as u32;
+                let value = self.try_read_u16(address)? as u32;
                 self.register.set_gpr(gpr_d, value);
                 self.register.increment_pc();
             }
@@ -440,7 +893,7 @@ impl GekkoInterpreter {
             }
             Instruction::Lfd(fr_d, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let value = raw_u64_to_f64(self.read_u64(address));
+                let value = raw_u64_to_f64(self.try_read_u64(address)?);
                 self.register.set_fpr_ps0(fr_d, value);
                 self.register.increment_pc();
             }
@@ -488,61 +941,303 @@ impl GekkoInterpreter {
             Instruction::Stfs(fr_s, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
                 let value_to_write = (self.register.get_fpr_ps0(fr_s) as f32).to_bits();
-                self.write_u32(address, value_to_write);
+                self.try_write_u32(address, value_to_write)?;
                 self.register.increment_pc();
             }
             Instruction::Lfs(fr_d, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let new_value = f32::from_bits(self.read_u32(address)) as f64;
+                let new_value = f32::from_bits(self.try_read_u32(address)?) as f64;
                 self.register.set_fpr_both(fr_d, new_value);
                 self.register.increment_pc();
             }
             Instruction::Stfdu(fr_s, gpr_a, d) => {
                 let address = (self.register.get_gpr(gpr_a) as i64 + (d as i64)) as u32;
                 let value_to_store = self.register.get_fpr_ps0(fr_s).to_bits();
-                self.write_u64(address, value_to_store);
+                self.try_write_u64(address, value_to_store)?;
                 self.register.set_gpr(gpr_a, address);
                 self.register.increment_pc();
             }
             Instruction::Stfd(fr_s, gpr_a, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
                 let value_to_store = self.register.get_fpr_ps0(fr_s).to_bits();
-                self.write_u64(address, value_to_store);
+                self.try_write_u64(address, value_to_store)?;
                 self.register.increment_pc();
             }
             Instruction::Psq_st(fr_s, gpr_a, w, i, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let qr = self.register.get_qr(i);
-                let stt = get_bit_section(qr, 29, 3) as u8;
-                let sts = get_bit_section(qr, 18, 6) as u8;
-                let c = get_size_for_quantized_type(stt);
-                if !w {
-                    // w == 0, to keep the order in the documentation
-                    let fpr_0 = self.register.get_fpr_ps0(fr_s);
-                    self.quantize_and_store(fpr_0, stt, sts, address);
-                    let fpr_1 = self.register.get_fpr_ps1(fr_s);
-                    self.quantize_and_store(fpr_1, stt, sts, address + c);
-                } else {
-                    todo!("psq_st for just one float");
-                }
+                self.store_paired_single(fr_s, address, w, i)?;
+                self.register.increment_pc();
+            }
+            Instruction::Psq_stu(fr_s, gpr_a, w, i, d) => {
+                let address = (self.register.get_gpr(gpr_a) as i64 + (d as i64)) as u32;
+                self.store_paired_single(fr_s, address, w, i)?;
+                self.register.set_gpr(gpr_a, address);
+                self.register.increment_pc();
+            }
+            Instruction::Psq_stx(fr_s, gpr_a, gpr_b, w, i) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                self.store_paired_single(fr_s, address, w, i)?;
                 self.register.increment_pc();
             }
-            Instruction::Psq_l(fr_s, gpr_a, w, i, d) => {
+            Instruction::Psq_stux(fr_s, gpr_a, gpr_b, w, i) => {
+                let address = self
+                    .register
+                    .get_gpr(gpr_a)
+                    .wrapping_add(self.register.get_gpr(gpr_b));
+                self.store_paired_single(fr_s, address, w, i)?;
+                self.register.set_gpr(gpr_a, address);
+                self.register.increment_pc();
+            }
+            Instruction::Psq_l(fr_d, gpr_a, w, i, d) => {
                 let address = self.register.compute_address_based_on_register(gpr_a, d);
-                let qr = self.register.get_qr(i);
-                let lt = get_bit_section(qr, 13, 3) as u8;
-                let ls = get_bit_section(qr, 2, 6) as u8;
-                let c = get_size_for_quantized_type(lt);
-                let fpr_0 = self.dequantize(address, lt, ls);
-                self.register.set_fpr_ps0(fr_s, fpr_0);
-                if !w {
-                    let fpr_1 = self.dequantize(address + c, lt, ls);
-                    self.register.set_fpr_ps1(fr_s, fpr_1);
+                self.load_paired_single(fr_d, address, w, i)?;
+                self.register.increment_pc();
+            }
+            Instruction::Psq_lu(fr_d, gpr_a, w, i, d) => {
+                let address = (self.register.get_gpr(gpr_a) as i64 + (d as i64)) as u32;
+                self.load_paired_single(fr_d, address, w, i)?;
+                self.register.set_gpr(gpr_a, address);
+                self.register.increment_pc();
+            }
+            Instruction::Psq_lx(fr_d, gpr_a, gpr_b, w, i) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                self.load_paired_single(fr_d, address, w, i)?;
+                self.register.increment_pc();
+            }
+            Instruction::Psq_lux(fr_d, gpr_a, gpr_b, w, i) => {
+                let address = self
+                    .register
+                    .get_gpr(gpr_a)
+                    .wrapping_add(self.register.get_gpr(gpr_b));
+                self.load_paired_single(fr_d, address, w, i)?;
+                self.register.set_gpr(gpr_a, address);
+                self.register.increment_pc();
+            }
+            Instruction::Ps_cmpu0(crf_d, fr_a, fr_b) => {
+                let a = self.register.get_fpr_ps0(fr_a);
+                let b = self.register.get_fpr_ps0(fr_b);
+                self.compare_float(crf_d, a, b, false);
+                self.register.increment_pc();
+            }
+            Instruction::Ps_cmpo0(crf_d, fr_a, fr_b) => {
+                let a = self.register.get_fpr_ps0(fr_a);
+                let b = self.register.get_fpr_ps0(fr_b);
+                self.compare_float(crf_d, a, b, true);
+                self.register.increment_pc();
+            }
+            Instruction::Ps_cmpu1(crf_d, fr_a, fr_b) => {
+                let a = self.register.get_fpr_ps1(fr_a);
+                let b = self.register.get_fpr_ps1(fr_b);
+                self.compare_float(crf_d, a, b, false);
+                self.register.increment_pc();
+            }
+            Instruction::Ps_cmpo1(crf_d, fr_a, fr_b) => {
+                let a = self.register.get_fpr_ps1(fr_a);
+                let b = self.register.get_fpr_ps1(fr_b);
+                self.compare_float(crf_d, a, b, true);
+                self.register.increment_pc();
+            }
+            Instruction::Ps_negx(fr_d, fr_b, rc) => {
+                let ps0 = -self.register.get_fpr_ps0(fr_b);
+                let ps1 = -self.register.get_fpr_ps1(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_mrx(fr_d, fr_b, rc) => {
+                let ps0 = self.register.get_fpr_ps0(fr_b);
+                let ps1 = self.register.get_fpr_ps1(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_nabsx(fr_d, fr_b, rc) => {
+                let ps0 = -self.register.get_fpr_ps0(fr_b).abs();
+                let ps1 = -self.register.get_fpr_ps1(fr_b).abs();
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_absx(fr_d, fr_b, rc) => {
+                let ps0 = self.register.get_fpr_ps0(fr_b).abs();
+                let ps1 = self.register.get_fpr_ps1(fr_b).abs();
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_merge00x(fr_d, fr_a, fr_b, rc) => {
+                let ps0 = self.register.get_fpr_ps0(fr_a);
+                let ps1 = self.register.get_fpr_ps0(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_merge01x(fr_d, fr_a, fr_b, rc) => {
+                let ps0 = self.register.get_fpr_ps0(fr_a);
+                let ps1 = self.register.get_fpr_ps1(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_merge10x(fr_d, fr_a, fr_b, rc) => {
+                let ps0 = self.register.get_fpr_ps1(fr_a);
+                let ps1 = self.register.get_fpr_ps0(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_merge11x(fr_d, fr_a, fr_b, rc) => {
+                let ps0 = self.register.get_fpr_ps1(fr_a);
+                let ps1 = self.register.get_fpr_ps1(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_sum0x(fr_d, fr_a, fr_b, fr_c, rc) => {
+                let sum = self.register.get_fpr_ps0(fr_a) + self.register.get_fpr_ps1(fr_b);
+                let ps1 = self.register.get_fpr_ps1(fr_c);
+                self.register.set_fpr_ps0(fr_d, sum as f32 as f64);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_sum1x(fr_d, fr_a, fr_b, fr_c, rc) => {
+                let ps0 = self.register.get_fpr_ps0(fr_c);
+                let sum = self.register.get_fpr_ps0(fr_a) + self.register.get_fpr_ps1(fr_b);
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, sum as f32 as f64);
+                if rc {
+                    self.register.update_cr1();
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Ps_selx(fr_d, fr_a, fr_b, fr_c, rc) => {
+                // NaN compare as false, and so select frB
+                let ps0 = if self.register.get_fpr_ps0(fr_a) >= 0.0 {
+                    self.register.get_fpr_ps0(fr_c)
                 } else {
-                    self.register.set_fpr_ps1(fr_s, 1.0);
+                    self.register.get_fpr_ps0(fr_b)
+                };
+                let ps1 = if self.register.get_fpr_ps1(fr_a) >= 0.0 {
+                    self.register.get_fpr_ps1(fr_c)
+                } else {
+                    self.register.get_fpr_ps1(fr_b)
+                };
+                self.register.set_fpr_ps0(fr_d, ps0);
+                self.register.set_fpr_ps1(fr_d, ps1);
+                if rc {
+                    self.register.update_cr1();
                 }
                 self.register.increment_pc();
             }
+            Instruction::Mfmsr(gpr_d) => {
+                self.register.set_gpr(gpr_d, self.register.msr);
+                self.register.increment_pc();
+            }
+            Instruction::Mtmsr(gpr_s) => {
+                self.register.msr = self.register.get_gpr(gpr_s);
+                self.register.increment_pc();
+            }
+            Instruction::Rfi => {
+                self.register.msr =
+                    (self.register.msr & !MSR_RFI_MASK) | (self.register.srr1 & MSR_RFI_MASK);
+                self.register.msr &= !MSR_POW;
+                self.register.pc = self.register.srr0 & !3;
+            }
+            Instruction::Sc => {
+                if !self.break_on_system_call {
+                    return Err(Exception::SystemCall);
+                };
+                break_data = BreakData::SystemCall;
+                self.register.increment_pc();
+            }
+            Instruction::Tw(to, gpr_a, gpr_b) => {
+                let a = self.register.get_gpr(gpr_a);
+                let b = self.register.get_gpr(gpr_b);
+                if check_trap_condition(to, a, b) {
+                    break_data = self.trap(TrapKind::Tw { to, gpr_a, gpr_b })?;
+                };
+                self.register.increment_pc();
+            }
+            Instruction::Twi(to, gpr_a, simm) => {
+                let a = self.register.get_gpr(gpr_a);
+                if check_trap_condition(to, a, simm as i32 as u32) {
+                    break_data = self.trap(TrapKind::Twi { to, gpr_a, simm })?;
+                };
+                self.register.increment_pc();
+            }
+            Instruction::Dcbf(_, _)
+            | Instruction::Dcbi(_, _)
+            | Instruction::Dcbst(_, _)
+            | Instruction::Dcbt(_, _)
+            | Instruction::Dcbtst(_, _) => {
+                // the data cache isn't emulated, the memory is always up to date
+                self.register.increment_pc();
+            }
+            Instruction::Dcbz(gpr_a, gpr_b) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b)
+                    & !(CACHE_BLOCK_SIZE - 1);
+                self.try_write(address, [0; CACHE_BLOCK_SIZE as usize])?;
+                self.register.increment_pc();
+            }
+            Instruction::Icbi(gpr_a, gpr_b) => {
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b);
+                self.invalidate_instruction_cache(address & !(CACHE_BLOCK_SIZE - 1));
+                self.register.increment_pc();
+            }
+            Instruction::Dcbz_l(gpr_a, gpr_b) => {
+                if self.register.hid2 & HID2_LCE == 0 {
+                    return Err(Exception::Program(ProgramReason::IllegalInstruction));
+                }
+                // the block is only allocated in the locked cache, the main memory is never
+                // written and any other address is a no-op
+                let address = self
+                    .register
+                    .compute_address_based_on_pair_of_register(gpr_a, gpr_b)
+                    & !(CACHE_BLOCK_SIZE - 1);
+                if let Ok(Location::LockedCache(_)) = self.locate(address, AccessKind::Store) {
+                    let _ = self.write_guest(address, &[0; CACHE_BLOCK_SIZE as usize]);
+                }
+                self.register.increment_pc();
+            }
+            Instruction::Sync | Instruction::Isync | Instruction::Eieio => {
+                // instructions are executed in order, there is nothing to wait for
+                self.register.increment_pc();
+            }
             Instruction::CustomBreak => {
                 break_data = BreakData::Break;
                 self.register.increment_pc();
@@ -551,34 +1246,178 @@ impl GekkoInterpreter {
         Ok(break_data)
     }
 
-    fn quantize_and_store(&mut self, fpr: f64, st_type: u8, _st_scale: u8, address: u32) {
-        match st_type {
-            0 => {
-                // no scaling
-                self.write_u32(address, u32::from_ne_bytes((fpr as f32).to_ne_bytes()));
-            }
-            4 => todo!("quantize_and_store for type 4"),
-            5 => todo!("quantize_and_store for type 5"),
-            6 => todo!("quantize_and_store for type 6"),
-            7 => todo!("quantize_and_store for type 7"),
-            _ => panic!(
-                "invalid value for st_type in quantize_and_store: {}",
-                st_type
-            ),
+    /// invalidate the instruction cache block at the effective address address, so that the
+    /// instructions written there are decoded again
+    pub fn invalidate_instruction_cache(&mut self, address: u32) {
+        if let Ok(Location::Physical(physical)) = self.locate(address, AccessKind::Fetch) {
+            self.invalidate_code(physical & !(CACHE_BLOCK_SIZE - 1), CACHE_BLOCK_SIZE);
+        };
+    }
+
+    /// forget every decoded instruction. The writes done by the interpreter invalidate the
+    /// instructions they overwrite, but this must be called after writing code directly in
+    /// GekkoInterpreter::memory.
+    pub fn flush_instruction_cache(&mut self) {
+        self.instruction_cache.clear();
+        self.block_cache.clear();
+    }
+
+    /// forget the decoded instructions and blocks overlapping the size bytes at physical
+    #[inline]
+    fn invalidate_code(&mut self, physical: u32, size: u32) {
+        self.instruction_cache.invalidate(physical, size);
+        self.block_cache.invalidate(physical, size);
+    }
+
+    /// a trap condition was met: either report it to the embedder or raise a program exception
+    fn trap(&mut self, kind: TrapKind) -> Result<BreakData, Exception> {
+        if self.break_on_trap {
+            Ok(BreakData::Trap {
+                pc: self.register.pc,
+                kind,
+            })
+        } else {
+            Err(Exception::Program(ProgramReason::Trap))
         }
     }
 
-    fn dequantize(&self, address: u32, l_type: u8, _l_scale: u8) -> f64 {
-        match l_type {
-            0 => {
-                let encoded_value = self.read_u32(address);
-                f32::from_ne_bytes((encoded_value).to_ne_bytes()) as f64
+    /// load count bytes at address into the registers starting at gpr_d, four bytes per register
+    /// from the most significant one, wrapping from r31 to r0. The bytes left in the last
+    /// register are cleared.
+    fn load_string(&mut self, gpr_d: u8, address: u32, count: u32) -> Result<(), Exception> {
+        let mut gpr = gpr_d;
+        let mut value = 0;
+        for i in 0..count {
+            value |= (self.try_read_u8(address.wrapping_add(i))? as u32) << (24 - (i % 4) * 8);
+            if i % 4 == 3 || i == count - 1 {
+                self.register.set_gpr(gpr, value);
+                gpr = (gpr + 1) % 32;
+                value = 0;
             }
-            4 => todo!("dequantize type 4"),
-            5 => todo!("dequantize type 5"),
-            6 => todo!("dequantize type 6"),
-            7 => todo!("dequantize type 7"),
-            _ => panic!("invalide value for l_type in dequantize: {}", l_type),
+        }
+        Ok(())
+    }
+
+    /// store count bytes to address from the registers starting at gpr_s, four bytes per register
+    /// from the most significant one, wrapping from r31 to r0
+    fn store_string(&mut self, gpr_s: u8, address: u32, count: u32) -> Result<(), Exception> {
+        for i in 0..count {
+            let gpr = ((gpr_s as u32 + i / 4) % 32) as u8;
+            let byte = (self.register.get_gpr(gpr) >> (24 - (i % 4) * 8)) as u8;
+            self.try_write_u8(address.wrapping_add(i), byte)?;
+        }
+        Ok(())
+    }
+
+    /// store ps0 (and ps1 if w is false) of fr_s at address, using the quantization register i
+    fn store_paired_single(
+        &mut self,
+        fr_s: u8,
+        address: u32,
+        w: bool,
+        i: u8,
+    ) -> Result<(), Exception> {
+        let qr = self.register.get_qr(i);
+        let stt = get_bit_section(qr, 29, 3) as u8;
+        let sts = get_bit_section(qr, 18, 6) as u8;
+        let c = get_size_for_quantized_type(stt);
+        let fpr_0 = self.register.get_fpr_ps0(fr_s);
+        self.quantize_and_store(fpr_0, stt, sts, address)?;
+        if !w {
+            // w == 0, to keep the order in the documentation
+            let fpr_1 = self.register.get_fpr_ps1(fr_s);
+            self.quantize_and_store(fpr_1, stt, sts, address + c)?;
+        }
+        Ok(())
+    }
+
+    /// load ps0 (and ps1 if w is false, otherwise ps1 is set to 1.0) of fr_d from address,
+    /// using the quantization register i
+    fn load_paired_single(
+        &mut self,
+        fr_d: u8,
+        address: u32,
+        w: bool,
+        i: u8,
+    ) -> Result<(), Exception> {
+        let qr = self.register.get_qr(i);
+        let lt = get_bit_section(qr, 13, 3) as u8;
+        let ls = get_bit_section(qr, 2, 6) as u8;
+        let c = get_size_for_quantized_type(lt);
+        let fpr_0 = self.dequantize(address, lt, ls)?;
+        self.register.set_fpr_ps0(fr_d, fpr_0);
+        if !w {
+            let fpr_1 = self.dequantize(address + c, lt, ls)?;
+            self.register.set_fpr_ps1(fr_d, fpr_1);
+        } else {
+            self.register.set_fpr_ps1(fr_d, 1.0);
+        }
+        Ok(())
+    }
+
+    /// convert fpr to the type st_type, multiplied by 2^st_scale, and store it at address.
+    /// integer types saturate to their bounds (and NaN is stored as 0)
+    fn quantize_and_store(
+        &mut self,
+        fpr: f64,
+        st_type: u8,
+        st_scale: u8,
+        address: u32,
+    ) -> Result<(), Exception> {
+        let scaled = fpr * get_quantization_scale(st_scale);
+        match st_type {
+            4 => self.try_write_u8(address, scaled as u8)?,
+            5 => self.try_write_u16(address, scaled as u16)?,
+            6 => self.try_write_u8(address, (scaled as i8) as u8)?,
+            7 => self.try_write_u16(address, (scaled as i16) as u16)?,
+            // float, without scaling. The reserved types 1 to 3 are handled the same, like in
+            // Dolphin.
+            _ => {
+                self.try_write_u32(address, u32::from_ne_bytes((fpr as f32).to_ne_bytes()))?;
+            }
+        };
+        Ok(())
+    }
+
+    /// read a value of type l_type at address, and divide it by 2^l_scale
+    fn dequantize(&self, address: u32, l_type: u8, l_scale: u8) -> Result<f64, Exception> {
+        let value = match l_type {
+            4 => self.try_read_u8(address)? as f64,
+            5 => self.try_read_u16(address)? as f64,
+            6 => (self.try_read_u8(address)? as i8) as f64,
+            7 => (self.try_read_u16(address)? as i16) as f64,
+            // float, without scaling, like the reserved types 1 to 3 (see quantize_and_store)
+            _ => {
+                let encoded_value = self.try_read_u32(address)?;
+                return Ok(f32::from_ne_bytes((encoded_value).to_ne_bytes()) as f64);
+            }
+        };
+        // the result is rounded to single precision, like any paired single value
+        Ok((value / get_quantization_scale(l_scale)) as f32 as f64)
+    }
+
+    /// compare two floats, and write the result in both the cr field crf_d and FPSCR[FPCC].
+    /// ordered comparison (fcmpo, ps_cmpo*) also signal quiet NaN as an invalid comparison.
+    fn compare_float(&mut self, crf_d: u8, a: f64, b: f64, ordered: bool) {
+        let f = if a.is_nan() || b.is_nan() {
+            0x1
+        } else if a < b {
+            0x8
+        } else if a > b {
+            0x4
+        } else {
+            0x2
+        };
+        self.register.cr[crf_d as usize] = f;
+        self.register.set_fpscr_fpcc(f);
+
+        if is_signaling_nan(a) || is_signaling_nan(b) {
+            self.register.set_fpscr_exception(FPSCR_VXSNAN);
+            if ordered && self.register.fpscr & FPSCR_VE == 0 {
+                self.register.set_fpscr_exception(FPSCR_VXVC);
+            }
+        } else if ordered && (a.is_nan() || b.is_nan()) {
+            self.register.set_fpscr_exception(FPSCR_VXVC);
         }
     }
 
@@ -595,74 +1434,275 @@ impl GekkoInterpreter {
         (ctr_ok, cond_ok)
     }
 
+    /// run the instructions, and the scheduled events when they are due, until one of them
+    /// return something else than BreakData::None
     pub fn run_until_event(&mut self) -> BreakData {
         loop {
-            match self.step().unwrap() {
+            // run a batch of instructions, up to the next scheduled event
+            let next_event_cycle = self.scheduler.next_event_cycle().unwrap_or(u64::MAX);
+            while self.counter < next_event_cycle {
+                let break_data = if self.use_blocks && !self.log {
+                    self.run_block(next_event_cycle - self.counter)
+                } else {
+                    self.step().unwrap()
+                };
+                match break_data {
+                    BreakData::None => continue,
+                    x => return x,
+                }
+            }
+            match self.run_due_events() {
+                BreakData::None => continue,
+                x => return x,
+            }
+        }
+    }
+
+    /// call the callback of every scheduled event that is due. Stop at the first one that
+    /// doesn't return BreakData::None, and return its result.
+    pub fn run_due_events(&mut self) -> BreakData {
+        while let Some(mut callback) = self.scheduler.pop_due_event(self.counter) {
+            match callback(self) {
                 BreakData::None => continue,
                 x => return x,
             }
         }
+        BreakData::None
     }
 
-    pub fn get_ram(&self) -> &Vec<u8> {
-        &self.ram
+    /// schedule callback to be called in delay cycles
+    pub fn schedule_in(&mut self, delay: u64, callback: EventCallback<M>) -> EventId {
+        self.scheduler.schedule_at(self.counter + delay, callback)
     }
 
+    /// return the region and the offset in it of address, or why it can't be translated. The
+    /// locked cache is only mapped for data accesses when HID2[LCE] is set, before any
+    /// translation.
     #[inline]
-    pub fn write_u32(&mut self, mut offset: u32, data: u32) {
-        offset -= BASE_RW_ADRESS;
-        for d in &data.to_be_bytes() {
-            self.ram[offset as usize] = *d;
-            offset += 1;
+    pub(crate) fn locate(
+        &self,
+        address: u32,
+        access: AccessKind,
+    ) -> Result<Location, TranslationFault> {
+        if access != AccessKind::Fetch
+            && address >= LOCKED_CACHE_ADRESS
+            && self.register.hid2 & HID2_LCE != 0
+        {
+            return Ok(Location::LockedCache(
+                (address - LOCKED_CACHE_ADRESS) as usize,
+            ));
+        };
+        self.bat_tables
+            .translate(&self.register, &self.memory, address, access)
+            .map(Location::Physical)
+    }
+
+    /// the address used to identify a reservation: the physical address in ram, or the effective
+    /// address in the locked cache
+    fn get_reservation_address(&self, address: u32, access: AccessKind) -> Result<u32, Exception> {
+        match self.locate(address, access) {
+            Ok(Location::Physical(physical)) => Ok(physical),
+            Ok(Location::LockedCache(_)) => Ok(address),
+            Err(fault) => Err(Exception::dsi(address, access == AccessKind::Store, fault)),
         }
     }
 
+    /// any write to the reserved block, even from the embedder or a DMA, make stwcx. fail
     #[inline]
-    pub fn write_u64(&mut self, mut offset: u32, data: u64) {
-        offset -= BASE_RW_ADRESS;
-        for d in &data.to_be_bytes() {
-            self.ram[offset as usize] = *d;
-            offset += 1;
+    fn clear_reservation(&mut self, physical: u32, size: u32) {
+        if let Some(reservation) = self.reservation {
+            let last_byte = physical.wrapping_add(size - 1);
+            if (physical & !(CACHE_BLOCK_SIZE - 1)) <= reservation
+                && reservation <= (last_byte & !(CACHE_BLOCK_SIZE - 1))
+            {
+                self.reservation = None;
+            }
+        };
+    }
+
+    /// read buffer.len() bytes at address, from the memory, the locked cache or the hardware
+    /// registers. Return why if they can't be accessed, an address translated to nothing being
+    /// reported as not mapped.
+    #[inline]
+    pub(crate) fn read_guest(
+        &self,
+        address: u32,
+        buffer: &mut [u8],
+        access: AccessKind,
+    ) -> Result<(), TranslationFault> {
+        let read = match self.locate(address, access)? {
+            Location::Physical(physical) => {
+                self.memory.read_bytes(physical, buffer).is_ok()
+                    // the instructions can't be fetched from the hardware registers
+                    || (access != AccessKind::Fetch
+                        && self.mmio.borrow_mut().read(physical, buffer))
+            }
+            Location::LockedCache(offset) => {
+                match self.locked_cache.get(offset..offset + buffer.len()) {
+                    Some(cache) => {
+                        buffer.copy_from_slice(cache);
+                        true
+                    }
+                    None => false,
+                }
+            }
+        };
+        if read {
+            Ok(())
+        } else {
+            Err(TranslationFault::NotMapped)
         }
     }
 
+    /// write data at address, to the memory, the locked cache or the hardware registers. Return
+    /// why if they can't be accessed, like read_guest.
     #[inline]
-    pub fn write_u8(&mut self, offset: u32, data: u8) {
-        self.ram[(offset - BASE_RW_ADRESS) as usize] = data;
+    pub(crate) fn write_guest(
+        &mut self,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), TranslationFault> {
+        let written = match self.locate(address, AccessKind::Store)? {
+            Location::Physical(physical) => {
+                #[cfg(feature = "jit")]
+                self.journal_write(Location::Physical(physical), data.len());
+                if self.memory.write_bytes(physical, data).is_ok() {
+                    self.clear_reservation(physical, data.len() as u32);
+                    self.invalidate_code(physical, data.len() as u32);
+                    true
+                } else {
+                    self.mmio.get_mut().write(physical, data)
+                }
+            }
+            Location::LockedCache(offset) => {
+                #[cfg(feature = "jit")]
+                self.journal_write(Location::LockedCache(offset), data.len());
+                match self.locked_cache.get_mut(offset..offset + data.len()) {
+                    Some(cache) => {
+                        cache.copy_from_slice(data);
+                        self.clear_reservation(address, data.len() as u32);
+                        true
+                    }
+                    None => false,
+                }
+            }
+        };
+        if written {
+            Ok(())
+        } else {
+            Err(TranslationFault::NotMapped)
+        }
+    }
+
+    #[inline]
+    fn try_read<const N: usize>(&self, address: u32) -> Result<[u8; N], Exception> {
+        let mut buffer = [0; N];
+        self.read_guest(address, &mut buffer, AccessKind::Load)
+            .map_err(|fault| Exception::dsi(address, false, fault))?;
+        Ok(buffer)
     }
 
+    /// fetch the instruction at address: return an ISI if it can't be
     #[inline]
-    pub fn read_u32(&self, mut offset: u32) -> u32 {
-        offset -= BASE_RW_ADRESS;
+    fn try_fetch(&self, address: u32) -> Result<u32, Exception> {
         let mut buffer = [0; 4];
-        for d in &mut buffer {
-            *d = self.ram[offset as usize];
-            offset += 1;
-        }
-        u32::from_be_bytes(buffer)
+        self.read_guest(address, &mut buffer, AccessKind::Fetch)
+            .map_err(|_| Exception::Isi { address })?;
+        Ok(u32::from_be_bytes(buffer))
     }
 
     #[inline]
-    pub fn read_u64(&self, mut offset: u32) -> u64 {
-        offset -= BASE_RW_ADRESS;
-        let mut buffer = [0; 8];
-        for d in &mut buffer {
-            *d = self.ram[offset as usize];
-            offset += 1;
-        }
-        u64::from_be_bytes(buffer)
+    fn try_write<const N: usize>(&mut self, address: u32, data: [u8; N]) -> Result<(), Exception> {
+        self.write_guest(address, &data)
+            .map_err(|fault| Exception::dsi(address, true, fault))
+    }
+
+    /// read from the guest point of view: return a DSI if the address isn't mapped
+    #[inline]
+    pub fn try_read_u8(&self, address: u32) -> Result<u8, Exception> {
+        Ok(self.try_read::<1>(address)?[0])
     }
 
     #[inline]
-    pub fn read_u16(&self, mut offset: u32) -> u16 {
-        offset -= BASE_RW_ADRESS;
-        let v1 = self.ram[offset as usize];
-        let v2 = self.ram[(offset as usize) + 1];
-        u16::from_be_bytes([v1, v2])
+    pub fn try_read_u16(&self, address: u32) -> Result<u16, Exception> {
+        Ok(u16::from_be_bytes(self.try_read(address)?))
+    }
+
+    #[inline]
+    pub fn try_read_u32(&self, address: u32) -> Result<u32, Exception> {
+        Ok(u32::from_be_bytes(self.try_read(address)?))
+    }
+
+    #[inline]
+    pub fn try_read_u64(&self, address: u32) -> Result<u64, Exception> {
+        Ok(u64::from_be_bytes(self.try_read(address)?))
+    }
+
+    /// write from the guest point of view: return a DSI if the address isn't mapped
+    #[inline]
+    pub fn try_write_u8(&mut self, address: u32, data: u8) -> Result<(), Exception> {
+        self.try_write(address, [data])
+    }
+
+    #[inline]
+    pub fn try_write_u16(&mut self, address: u32, data: u16) -> Result<(), Exception> {
+        self.try_write(address, data.to_be_bytes())
+    }
+
+    #[inline]
+    pub fn try_write_u32(&mut self, address: u32, data: u32) -> Result<(), Exception> {
+        self.try_write(address, data.to_be_bytes())
+    }
+
+    #[inline]
+    pub fn try_write_u64(&mut self, address: u32, data: u64) -> Result<(), Exception> {
+        self.try_write(address, data.to_be_bytes())
+    }
+
+    #[inline]
+    pub fn write_u32(&mut self, offset: u32, data: u32) {
+        self.try_write_u32(offset, data).unwrap()
+    }
+
+    #[inline]
+    pub fn write_u64(&mut self, offset: u32, data: u64) {
+        self.try_write_u64(offset, data).unwrap()
+    }
+
+    #[inline]
+    pub fn write_u16(&mut self, offset: u32, data: u16) {
+        self.try_write_u16(offset, data).unwrap()
+    }
+
+    #[inline]
+    pub fn write_u8(&mut self, offset: u32, data: u8) {
+        self.try_write_u8(offset, data).unwrap()
+    }
+
+    #[inline]
+    pub fn read_u32(&self, offset: u32) -> u32 {
+        self.try_read_u32(offset).unwrap()
+    }
+
+    #[inline]
+    pub fn read_u64(&self, offset: u32) -> u64 {
+        self.try_read_u64(offset).unwrap()
+    }
+
+    #[inline]
+    pub fn read_u16(&self, offset: u32) -> u16 {
+        self.try_read_u16(offset).unwrap()
     }
 
     #[inline]
     pub fn read_u8(&self, offset: u32) -> u8 {
-        self.ram[(offset - BASE_RW_ADRESS) as usize]
+        self.try_read_u8(offset).unwrap()
+    }
+}
+
+impl Default for GekkoInterpreter {
+    /// an interpreter with the memory of a retail GameCube
+    fn default() -> Self {
+        GekkoInterpreter::with_memory(MemoryMap::default())
     }
 }
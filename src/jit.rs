@@ -0,0 +1,966 @@
+use crate::interpreter::Location;
+use crate::register::GekkoRegister;
+use crate::util::{make_rotation_mask, u8_get_bit};
+use crate::{BreakData, Exception, GekkoInterpreter, GuestMemory, Instruction};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, InstBuilder, MemFlags, SigRef, Signature, Type, Value,
+};
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use std::mem::{offset_of, replace, ManuallyDrop};
+
+/// the size of the code compiled by a Jit before it is rebuilt, as Cranelift can't free a single
+/// function
+const MAX_CODE_SIZE: usize = 16 << 20;
+
+/// returned by the helpers called by the compiled code when the block must stop after the
+/// instruction. The lower 32 bits hold the value loaded.
+const STOP: u64 = 1 << 32;
+
+/// the host code of a block. It is called with the interpreter and the address of the block,
+/// and return the number of instructions it ran.
+#[derive(Clone, Copy)]
+pub(crate) struct CompiledCode {
+    function: unsafe extern "C" fn(*mut u8, u32) -> u32,
+}
+
+/// The compiler of the blocks to host code, with Cranelift. The integer instructions, the loads
+/// and stores and the branch ending the block are compiled. The accesses to the memory go
+/// through helpers, and the other instructions are run by calling the interpreter. In between,
+/// the general purpose registers and the condition register fields stay in host registers.
+pub(crate) struct Jit {
+    // freed with the Jit, once the blocks are forgotten
+    module: ManuallyDrop<JITModule>,
+    context: Context,
+    builder_context: FunctionBuilderContext,
+    // the size of the code compiled so far, and the size at which the Jit is rebuilt
+    code_size: usize,
+    pub code_limit: usize,
+    // the generation of the block cache when the block being run started, and what stopped it
+    generation: u64,
+    break_data: BreakData,
+}
+
+impl Jit {
+    /// a Jit for the host, if it is supported by Cranelift
+    pub fn new() -> Option<Jit> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").ok()?;
+        flags.set("is_pic", "false").ok()?;
+        let isa = cranelift_native::builder()
+            .ok()?
+            .finish(settings::Flags::new(flags))
+            .ok()?;
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        Some(Jit {
+            context: module.make_context(),
+            module: ManuallyDrop::new(module),
+            builder_context: FunctionBuilderContext::new(),
+            code_size: 0,
+            code_limit: MAX_CODE_SIZE,
+            generation: 0,
+            break_data: BreakData::None,
+        })
+    }
+
+    /// compile the block id of the interpreter on M, if it has an instruction to compile. Return
+    /// None if Cranelift fails, so that the block is interpreted.
+    pub fn compile<M: GuestMemory>(
+        &mut self,
+        id: usize,
+        instructions: &[Instruction],
+    ) -> Option<CompiledCode> {
+        if !instructions.iter().any(is_compiled) {
+            return None;
+        };
+        let pointer = self.module.target_config().pointer_type();
+        let call_conv = self.module.target_config().default_call_conv;
+        let signature = &mut self.context.func.signature;
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.returns.push(AbiParam::new(types::I32));
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let (interpreter, pc) = (
+            builder.block_params(entry)[0],
+            builder.block_params(entry)[1],
+        );
+        let helpers = Helpers::new::<M>(&mut builder, pointer, call_conv);
+        let mut lowering = Lowering {
+            builder,
+            pointer,
+            interpreter,
+            pc,
+            register: offset_of!(GekkoInterpreter<M>, register),
+            helpers,
+            gprs: [None; 32],
+            modified_gprs: [false; 32],
+            crs: [None; 8],
+            modified_crs: [false; 8],
+        };
+        let mut next_pc = None;
+        for (index, instruction) in instructions.iter().enumerate() {
+            next_pc = lowering.lower(id, index, *instruction);
+        }
+        lowering.end(instructions.len(), next_pc);
+        lowering.builder.finalize();
+
+        let code = self.define();
+        self.module.clear_context(&mut self.context);
+        Some(CompiledCode {
+            // the signature declared above
+            function: unsafe {
+                std::mem::transmute::<*const u8, unsafe extern "C" fn(*mut u8, u32) -> u32>(code?)
+            },
+        })
+    }
+
+    // compile the function in the context, and return its address
+    fn define(&mut self) -> Option<*const u8> {
+        let id = self
+            .module
+            .declare_anonymous_function(&self.context.func.signature)
+            .ok()?;
+        self.module.define_function(id, &mut self.context).ok()?;
+        self.code_size += self
+            .context
+            .compiled_code()
+            .map_or(0, |code| code.code_info().total_size as usize);
+        self.module.finalize_definitions().ok()?;
+        Some(self.module.get_finalized_function(id))
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        // the blocks, and their compiled code, are forgotten with the Jit
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+/// whether instruction is compiled, instead of being run by the interpreter. The branches always
+/// end the block.
+fn is_compiled(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Addi(..)
+            | Instruction::Addis(..)
+            | Instruction::Addx(_, _, _, false, _)
+            | Instruction::Subfx(_, _, _, false, _)
+            | Instruction::Ori(..)
+            | Instruction::Orx(..)
+            | Instruction::Nor(..)
+            | Instruction::Andidot(..)
+            | Instruction::Rlwinmx(..)
+            | Instruction::Rlwimix(..)
+            | Instruction::Extsbx(..)
+            | Instruction::Cmp(..)
+            | Instruction::Cmpi(..)
+            | Instruction::Cmpl(..)
+            | Instruction::Cmpli(..)
+            | Instruction::Lwz(..)
+            | Instruction::Lwzu(..)
+            | Instruction::Lwzx(..)
+            | Instruction::Lbz(..)
+            | Instruction::Lbzu(..)
+            | Instruction::Lhz(..)
+            | Instruction::Stw(..)
+            | Instruction::Stwu(..)
+            | Instruction::Stwx(..)
+            | Instruction::Stb(..)
+            | Instruction::Stbu(..)
+            | Instruction::Bx(..)
+            | Instruction::Bcx(..)
+            | Instruction::Bclrx(..)
+    )
+}
+
+// the helpers called by the compiled code, with their signature and address for M
+struct Helpers {
+    load: (SigRef, usize),
+    store: (SigRef, usize),
+    interpret: (SigRef, usize),
+}
+
+impl Helpers {
+    fn new<M: GuestMemory>(
+        builder: &mut FunctionBuilder,
+        pointer: Type,
+        call_conv: CallConv,
+    ) -> Helpers {
+        let mut import = |params: &[Type], address: usize| {
+            let mut signature = Signature::new(call_conv);
+            signature.params.push(AbiParam::new(pointer));
+            signature
+                .params
+                .extend(params.iter().map(|param| AbiParam::new(*param)));
+            signature.returns.push(AbiParam::new(types::I64));
+            (builder.import_signature(signature), address)
+        };
+        let i32 = types::I32;
+        Helpers {
+            load: import(&[i32, i32, i32], load::<M> as *const () as usize),
+            store: import(&[i32, i32, i32, i32], store::<M> as *const () as usize),
+            interpret: import(&[i32, pointer, i32], interpret::<M> as *const () as usize),
+        }
+    }
+}
+
+// the state of the guest registers while building the code of a block: each general purpose
+// register and condition register field is loaded on its first use, and the modified ones are
+// stored before running an instruction with the interpreter and when returning
+struct Lowering<'a> {
+    builder: FunctionBuilder<'a>,
+    pointer: Type,
+    // the parameters: the interpreter, and the address of the block
+    interpreter: Value,
+    pc: Value,
+    // the offset of GekkoInterpreter::register
+    register: usize,
+    helpers: Helpers,
+    gprs: [Option<Value>; 32],
+    modified_gprs: [bool; 32],
+    crs: [Option<Value>; 8],
+    modified_crs: [bool; 8],
+}
+
+impl Lowering<'_> {
+    fn load_register(&mut self, kind: Type, offset: usize) -> Value {
+        self.builder.ins().load(
+            kind,
+            MemFlags::trusted(),
+            self.interpreter,
+            (self.register + offset) as i32,
+        )
+    }
+
+    fn store_register(&mut self, value: Value, offset: usize) {
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            value,
+            self.interpreter,
+            (self.register + offset) as i32,
+        );
+    }
+
+    fn get(&mut self, nb: u8) -> Value {
+        let nb = nb as usize;
+        if let Some(value) = self.gprs[nb] {
+            return value;
+        };
+        let value = self.load_register(types::I32, GekkoRegister::GPR_OFFSET + nb * 4);
+        self.gprs[nb] = Some(value);
+        value
+    }
+
+    // the value of rA, or 0 for r0, as used by addi and the addresses
+    fn get_or_zero(&mut self, nb: u8) -> Value {
+        if nb == 0 {
+            self.builder.ins().iconst(types::I32, 0)
+        } else {
+            self.get(nb)
+        }
+    }
+
+    fn set(&mut self, nb: u8, value: Value) {
+        self.gprs[nb as usize] = Some(value);
+        self.modified_gprs[nb as usize] = true;
+    }
+
+    fn get_cr(&mut self, field: u8) -> Value {
+        let field = field as usize;
+        if let Some(value) = self.crs[field] {
+            return value;
+        };
+        let value = self.load_register(types::I8, offset_of!(GekkoRegister, cr) + field);
+        self.crs[field] = Some(value);
+        value
+    }
+
+    fn set_cr(&mut self, field: u8, value: Value) {
+        self.crs[field as usize] = Some(value);
+        self.modified_crs[field as usize] = true;
+    }
+
+    fn store_modified(&mut self) {
+        for nb in 0..32 {
+            if let (true, Some(value)) = (self.modified_gprs[nb], self.gprs[nb]) {
+                self.store_register(value, GekkoRegister::GPR_OFFSET + nb * 4);
+            };
+        }
+        for field in 0..8 {
+            if let (true, Some(value)) = (self.modified_crs[field], self.crs[field]) {
+                self.store_register(value, offset_of!(GekkoRegister, cr) + field);
+            };
+        }
+    }
+
+    // store the modified registers, and load them again after, as the interpreter may use and
+    // change any of them
+    fn flush(&mut self) {
+        self.store_modified();
+        self.gprs = [None; 32];
+        self.modified_gprs = [false; 32];
+        self.crs = [None; 8];
+        self.modified_crs = [false; 8];
+    }
+
+    // the effective address of the instruction index
+    fn address(&mut self, index: usize) -> Value {
+        self.builder.ins().iadd_imm(self.pc, index as i64 * 4)
+    }
+
+    // a field of the condition register, from the result of a comparison and XER[SO]
+    fn compare(&mut self, lesser: Value, greater: Value, equal: Value) -> Value {
+        let lesser = self.builder.ins().ishl_imm(lesser, 3);
+        let greater = self.builder.ins().ishl_imm(greater, 2);
+        let equal = self.builder.ins().ishl_imm(equal, 1);
+        let xer = self.load_register(types::I32, offset_of!(GekkoRegister, xer));
+        let so = self.builder.ins().ushr_imm(xer, 31);
+        let so = self.builder.ins().ireduce(types::I8, so);
+        let field = self.builder.ins().bor(lesser, greater);
+        let field = self.builder.ins().bor(field, equal);
+        self.builder.ins().bor(field, so)
+    }
+
+    fn update_cr0(&mut self, value: Value) {
+        let lesser = self.builder.ins().icmp_imm(IntCC::SignedLessThan, value, 0);
+        let greater = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::SignedGreaterThan, value, 0);
+        let equal = self.builder.ins().icmp_imm(IntCC::Equal, value, 0);
+        let field = self.compare(lesser, greater, equal);
+        self.set_cr(0, field);
+    }
+
+    fn compare_into(&mut self, crf_d: u8, a: Value, b: Value, signed: bool) {
+        let (lesser, greater) = if signed {
+            (IntCC::SignedLessThan, IntCC::SignedGreaterThan)
+        } else {
+            (IntCC::UnsignedLessThan, IntCC::UnsignedGreaterThan)
+        };
+        let lesser = self.builder.ins().icmp(lesser, a, b);
+        let greater = self.builder.ins().icmp(greater, a, b);
+        let equal = self.builder.ins().icmp(IntCC::Equal, a, b);
+        let field = self.compare(lesser, greater, equal);
+        self.set_cr(crf_d, field);
+    }
+
+    // call a helper with the interpreter and arguments, and return its result. If it stops the
+    // block, the registers are stored and the function return executed.
+    fn call(&mut self, helper: (SigRef, usize), arguments: &[Value], executed: usize) -> Value {
+        let callee = self.builder.ins().iconst(self.pointer, helper.1 as i64);
+        let mut all_arguments = vec![self.interpreter];
+        all_arguments.extend_from_slice(arguments);
+        let call = self
+            .builder
+            .ins()
+            .call_indirect(helper.0, callee, &all_arguments);
+        let result = self.builder.inst_results(call)[0];
+        let stop =
+            self.builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, result, STOP as i64);
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(stop, exit, &[], next, &[]);
+        self.builder.seal_block(exit);
+        self.builder.seal_block(next);
+        self.builder.switch_to_block(exit);
+        self.store_modified();
+        let executed = self.builder.ins().iconst(types::I32, executed as i64);
+        self.builder.ins().return_(&[executed]);
+        self.builder.switch_to_block(next);
+        result
+    }
+
+    // the value of size bytes loaded at address by the instruction index
+    fn load(&mut self, index: usize, address: Value, size: u32) -> Value {
+        let pc = self.address(index);
+        let size = self.builder.ins().iconst(types::I32, size as i64);
+        let result = self.call(self.helpers.load, &[pc, address, size], index + 1);
+        self.builder.ins().ireduce(types::I32, result)
+    }
+
+    fn store(&mut self, index: usize, address: Value, value: Value, size: u32) {
+        let pc = self.address(index);
+        let size = self.builder.ins().iconst(types::I32, size as i64);
+        self.call(self.helpers.store, &[pc, address, value, size], index + 1);
+    }
+
+    fn interpret(&mut self, id: usize, index: usize) {
+        self.flush();
+        let pc = self.address(index);
+        let id = self.builder.ins().iconst(self.pointer, id as i64);
+        let index_value = self.builder.ins().iconst(types::I32, index as i64);
+        self.call(self.helpers.interpret, &[pc, id, index_value], index + 1);
+    }
+
+    // whether a conditional branch is taken, None if it always is. CTR is decremented first,
+    // like in check_and_apply_conditional_jump.
+    fn branch_condition(&mut self, bo: u8, bi: u8) -> Option<Value> {
+        let mut condition = None;
+        if !u8_get_bit(bo, 7 - 2) {
+            let ctr = self.load_register(types::I32, offset_of!(GekkoRegister, ctr));
+            let ctr = self.builder.ins().iadd_imm(ctr, -1);
+            self.store_register(ctr, offset_of!(GekkoRegister, ctr));
+            let ctr_condition = if u8_get_bit(bo, 7 - 1) {
+                IntCC::Equal
+            } else {
+                IntCC::NotEqual
+            };
+            condition = Some(self.builder.ins().icmp_imm(ctr_condition, ctr, 0));
+        };
+        if !u8_get_bit(bo, 7 - 4) {
+            let field = self.get_cr(bi / 4);
+            let bit = self.builder.ins().ushr_imm(field, (3 - bi % 4) as i64);
+            let bit = self.builder.ins().band_imm(bit, 1);
+            let bit_condition = if u8_get_bit(bo, 7 - 3) {
+                IntCC::NotEqual
+            } else {
+                IntCC::Equal
+            };
+            let bit_ok = self.builder.ins().icmp_imm(bit_condition, bit, 0);
+            condition = Some(match condition {
+                Some(ctr_ok) => self.builder.ins().band(ctr_ok, bit_ok),
+                None => bit_ok,
+            });
+        };
+        condition
+    }
+
+    // the next pc of the branch at address to target, updating LR if lk
+    fn branch(&mut self, address: Value, taken: Option<Value>, target: Value, lk: bool) -> Value {
+        let next = self.builder.ins().iadd_imm(address, 4);
+        match taken {
+            None => {
+                if lk {
+                    self.store_register(next, offset_of!(GekkoRegister, lr));
+                };
+                target
+            }
+            Some(taken) => {
+                if lk {
+                    let lr = self.load_register(types::I32, offset_of!(GekkoRegister, lr));
+                    let lr = self.builder.ins().select(taken, next, lr);
+                    self.store_register(lr, offset_of!(GekkoRegister, lr));
+                };
+                self.builder.ins().select(taken, target, next)
+            }
+        }
+    }
+
+    // the same operations as GekkoInterpreter::execute, for the instruction index of the block
+    // id. Return the next pc for the branches.
+    fn lower(&mut self, id: usize, index: usize, instruction: Instruction) -> Option<Value> {
+        if !is_compiled(&instruction) {
+            self.interpret(id, index);
+            return None;
+        };
+        match instruction {
+            Instruction::Addi(gpr_d, gpr_a, simm) => {
+                let a = self.get_or_zero(gpr_a);
+                let d = self.builder.ins().iadd_imm(a, simm as i64);
+                self.set(gpr_d, d);
+            }
+            Instruction::Addis(gpr_d, gpr_a, simm) => {
+                let a = self.get_or_zero(gpr_a);
+                let d = self.builder.ins().iadd_imm(a, ((simm as u32) << 16) as i64);
+                self.set(gpr_d, d);
+            }
+            Instruction::Addx(gpr_d, gpr_a, gpr_b, _, rc) => {
+                let (a, b) = (self.get(gpr_a), self.get(gpr_b));
+                let d = self.builder.ins().iadd(a, b);
+                self.set(gpr_d, d);
+                if rc {
+                    self.update_cr0(d);
+                };
+            }
+            Instruction::Subfx(gpr_d, gpr_a, gpr_b, _, rc) => {
+                let (a, b) = (self.get(gpr_a), self.get(gpr_b));
+                let d = self.builder.ins().isub(b, a);
+                self.set(gpr_d, d);
+                if rc {
+                    self.update_cr0(d);
+                };
+            }
+            Instruction::Ori(gpr_s, gpr_a, uimm) => {
+                // the operands are swapped, like in the interpreter
+                let a = self.get(gpr_a);
+                let s = self.builder.ins().bor_imm(a, uimm as i64);
+                self.set(gpr_s, s);
+            }
+            Instruction::Orx(gpr_s, gpr_a, gpr_b, rc) => {
+                let (s, b) = (self.get(gpr_s), self.get(gpr_b));
+                let a = self.builder.ins().bor(s, b);
+                self.set(gpr_a, a);
+                if rc {
+                    self.update_cr0(a);
+                };
+            }
+            Instruction::Nor(gpr_s, gpr_a, gpr_b, rc) => {
+                let (s, b) = (self.get(gpr_s), self.get(gpr_b));
+                let or = self.builder.ins().bor(s, b);
+                let a = self.builder.ins().bnot(or);
+                self.set(gpr_a, a);
+                if rc {
+                    self.update_cr0(a);
+                };
+            }
+            Instruction::Andidot(gpr_s, gpr_a, uimm) => {
+                // the operands are swapped, like in the interpreter
+                let a = self.get(gpr_a);
+                let s = self.builder.ins().band_imm(a, uimm as i64);
+                self.set(gpr_s, s);
+                self.update_cr0(s);
+            }
+            Instruction::Rlwinmx(gpr_s, gpr_a, sh, mb, me, rc) => {
+                let mask = make_rotation_mask(mb as u32, me as u32);
+                let s = self.get(gpr_s);
+                let rotated = self.builder.ins().rotl_imm(s, sh as i64);
+                let a = self.builder.ins().band_imm(rotated, mask as i64);
+                self.set(gpr_a, a);
+                if rc {
+                    self.update_cr0(a);
+                };
+            }
+            Instruction::Rlwimix(gpr_s, gpr_a, sh, mb, me, rc) => {
+                let mask = make_rotation_mask(mb as u32, me as u32);
+                let (s, a) = (self.get(gpr_s), self.get(gpr_a));
+                let rotated = self.builder.ins().rotl_imm(s, sh as i64);
+                let inserted = self.builder.ins().band_imm(rotated, mask as i64);
+                let kept = self.builder.ins().band_imm(a, !mask as i64);
+                let a = self.builder.ins().bor(inserted, kept);
+                self.set(gpr_a, a);
+                if rc {
+                    self.update_cr0(a);
+                };
+            }
+            Instruction::Extsbx(gpr_s, gpr_a, rc) => {
+                let s = self.get(gpr_s);
+                let byte = self.builder.ins().ireduce(types::I8, s);
+                let a = self.builder.ins().sextend(types::I32, byte);
+                self.set(gpr_a, a);
+                if rc {
+                    self.update_cr0(a);
+                };
+            }
+            Instruction::Cmp(crf_d, gpr_a, gpr_b) | Instruction::Cmpl(crf_d, gpr_a, gpr_b) => {
+                let (a, b) = (self.get(gpr_a), self.get(gpr_b));
+                let signed = matches!(instruction, Instruction::Cmp(..));
+                self.compare_into(crf_d, a, b, signed);
+            }
+            Instruction::Cmpi(crf_d, gpr_a, simm) => {
+                let a = self.get(gpr_a);
+                let b = self
+                    .builder
+                    .ins()
+                    .iconst(types::I32, simm as i32 as u32 as i64);
+                self.compare_into(crf_d, a, b, true);
+            }
+            Instruction::Cmpli(crf_d, gpr_a, uimm) => {
+                let a = self.get(gpr_a);
+                let b = self.builder.ins().iconst(types::I32, uimm as i64);
+                self.compare_into(crf_d, a, b, false);
+            }
+            Instruction::Lwz(gpr_d, gpr_a, d)
+            | Instruction::Lbz(gpr_d, gpr_a, d)
+            | Instruction::Lhz(gpr_d, gpr_a, d) => {
+                let a = self.get_or_zero(gpr_a);
+                let address = self.builder.ins().iadd_imm(a, d as i64);
+                let size = match instruction {
+                    Instruction::Lbz(..) => 1,
+                    Instruction::Lhz(..) => 2,
+                    _ => 4,
+                };
+                let value = self.load(index, address, size);
+                self.set(gpr_d, value);
+            }
+            Instruction::Lwzu(gpr_d, gpr_a, d) | Instruction::Lbzu(gpr_d, gpr_a, d) => {
+                // like in the interpreter, lwzu use r0 as a base, but not lbzu
+                let a = match instruction {
+                    Instruction::Lwzu(..) => self.get(gpr_a),
+                    _ => self.get_or_zero(gpr_a),
+                };
+                let address = self.builder.ins().iadd_imm(a, d as i64);
+                let size = if let Instruction::Lbzu(..) = instruction {
+                    1
+                } else {
+                    4
+                };
+                let value = self.load(index, address, size);
+                self.set(gpr_d, value);
+                self.set(gpr_a, address);
+            }
+            Instruction::Lwzx(gpr_d, gpr_a, gpr_b) => {
+                let (a, b) = (self.get_or_zero(gpr_a), self.get(gpr_b));
+                let address = self.builder.ins().iadd(a, b);
+                let value = self.load(index, address, 4);
+                self.set(gpr_d, value);
+            }
+            Instruction::Stw(gpr_s, gpr_a, d)
+            | Instruction::Stb(gpr_s, gpr_a, d)
+            | Instruction::Stwu(gpr_s, gpr_a, d)
+            | Instruction::Stbu(gpr_s, gpr_a, d) => {
+                let a = self.get_or_zero(gpr_a);
+                let address = self.builder.ins().iadd_imm(a, d as i64);
+                let s = self.get(gpr_s);
+                let size = match instruction {
+                    Instruction::Stb(..) | Instruction::Stbu(..) => 1,
+                    _ => 4,
+                };
+                self.store(index, address, s, size);
+                if let Instruction::Stwu(..) | Instruction::Stbu(..) = instruction {
+                    self.set(gpr_a, address);
+                };
+            }
+            Instruction::Stwx(gpr_s, gpr_a, gpr_b) => {
+                let (a, b) = (self.get_or_zero(gpr_a), self.get(gpr_b));
+                let address = self.builder.ins().iadd(a, b);
+                let s = self.get(gpr_s);
+                self.store(index, address, s, 4);
+            }
+            Instruction::Bx(li, aa, lk) => {
+                let address = self.address(index);
+                let target = if aa {
+                    self.builder
+                        .ins()
+                        .iconst(types::I32, (li << 2) as u32 as i64)
+                } else {
+                    self.builder.ins().iadd_imm(address, (li as i64) << 2)
+                };
+                return Some(self.branch(address, None, target, lk));
+            }
+            Instruction::Bcx(bo, bi, bd, aa, lk) => {
+                let address = self.address(index);
+                let taken = self.branch_condition(bo, bi);
+                let target = if aa {
+                    self.builder
+                        .ins()
+                        .iconst(types::I32, ((bd as i32) << 2) as u32 as i64)
+                } else {
+                    self.builder.ins().iadd_imm(address, (bd as i64) << 2)
+                };
+                return Some(self.branch(address, taken, target, lk));
+            }
+            Instruction::Bclrx(bo, bi, lk) => {
+                let address = self.address(index);
+                let taken = self.branch_condition(bo, bi);
+                let lr = self.load_register(types::I32, offset_of!(GekkoRegister, lr));
+                let target = self.builder.ins().band_imm(lr, !3);
+                return Some(self.branch(address, taken, target, lk));
+            }
+            _ => unreachable!("{:?} can't be compiled", instruction),
+        };
+        None
+    }
+
+    // the end of a block of length instructions: set pc, store the registers and return
+    fn end(&mut self, length: usize, next_pc: Option<Value>) {
+        let next_pc = match next_pc {
+            Some(next_pc) => next_pc,
+            None => self.address(length),
+        };
+        self.store_register(next_pc, offset_of!(GekkoRegister, pc));
+        self.store_modified();
+        let length = self.builder.ins().iconst(types::I32, length as i64);
+        self.builder.ins().return_(&[length]);
+    }
+}
+
+// the helpers called by the compiled code, with the interpreter running it and the address of
+// the instruction. They return STOP if the block must stop after it.
+
+unsafe extern "C" fn load<M: GuestMemory>(
+    interpreter: *mut GekkoInterpreter<M>,
+    pc: u32,
+    address: u32,
+    size: u32,
+) -> u64 {
+    let interpreter = &mut *interpreter;
+    interpreter.register.pc = pc;
+    let value = match size {
+        1 => interpreter.try_read_u8(address).map(u32::from),
+        2 => interpreter.try_read_u16(address).map(u32::from),
+        _ => interpreter.try_read_u32(address),
+    };
+    match value {
+        Ok(value) => {
+            interpreter.register.increment_pc();
+            interpreter.finish_compiled(Ok(BreakData::None), pc) | value as u64
+        }
+        Err(exception) => interpreter.finish_compiled(Err(exception), pc),
+    }
+}
+
+unsafe extern "C" fn store<M: GuestMemory>(
+    interpreter: *mut GekkoInterpreter<M>,
+    pc: u32,
+    address: u32,
+    value: u32,
+    size: u32,
+) -> u64 {
+    let interpreter = &mut *interpreter;
+    interpreter.register.pc = pc;
+    let result = match size {
+        1 => interpreter.try_write_u8(address, value as u8),
+        _ => interpreter.try_write_u32(address, value),
+    };
+    let result = result.map(|()| {
+        interpreter.register.increment_pc();
+        BreakData::None
+    });
+    interpreter.finish_compiled(result, pc)
+}
+
+unsafe extern "C" fn interpret<M: GuestMemory>(
+    interpreter: *mut GekkoInterpreter<M>,
+    pc: u32,
+    id: usize,
+    index: u32,
+) -> u64 {
+    let interpreter = &mut *interpreter;
+    interpreter.register.pc = pc;
+    let instruction = interpreter.block_cache.instructions(id)[index as usize];
+    let result = interpreter.execute(instruction);
+    interpreter.finish_compiled(result, pc)
+}
+
+// everything the compiled code could change in the registers
+type IntegerState = ([u32; 32], [u32; 10], [u8; 8], Option<u32>);
+
+/// The JIT, for the blocks run by run_block. It is only built with the jit feature.
+impl<M: GuestMemory> GekkoInterpreter<M> {
+    /// compile the blocks to host code from now on, for run_block (so only with use_blocks). The
+    /// blocks already decoded are forgotten, so that they are compiled too. Return false if the
+    /// host isn't supported by Cranelift.
+    pub fn enable_jit(&mut self) -> bool {
+        if self.jit.is_none() {
+            self.block_cache.clear();
+            self.jit = Jit::new();
+        };
+        self.jit.is_some()
+    }
+
+    /// interpret the blocks again, and free their compiled code
+    pub fn disable_jit(&mut self) {
+        self.block_cache.clear();
+        self.jit = None;
+    }
+
+    /// rebuild the Jit once it compiled too much code, freeing it with all the blocks. Called
+    /// before decoding a block, as the blocks of the previous code can't be kept.
+    pub(crate) fn reclaim_jit_code(&mut self) {
+        if let Some(jit) = &self.jit {
+            if jit.code_size >= jit.code_limit {
+                let code_limit = jit.code_limit;
+                self.block_cache.clear();
+                self.jit = Jit::new();
+                if let Some(jit) = &mut self.jit {
+                    jit.code_limit = code_limit;
+                };
+            };
+        };
+    }
+
+    /// compile the new block id, if the JIT is enabled
+    pub(crate) fn compile_block(&mut self, id: usize) -> Option<CompiledCode> {
+        let instructions = self.block_cache.instructions(id);
+        self.jit.as_mut()?.compile::<M>(id, instructions)
+    }
+
+    /// run the compiled code of the block id at pc, and return the number of instructions it ran
+    /// and what stopped it, like interpret_block. With jit_lockstep, the block is then run
+    /// again by the interpreter from the same state, and the results compared.
+    pub(crate) fn run_compiled(
+        &mut self,
+        id: usize,
+        code: CompiledCode,
+        generation: u64,
+    ) -> (usize, BreakData) {
+        if !self.jit_lockstep {
+            return self.call_compiled(code, generation);
+        };
+        let pc = self.register.pc;
+        let instructions = self.block_cache.instructions(id).to_vec();
+        let register = self.register.clone();
+        let time = self.time.clone();
+        let (reservation, decrementer_pending) = (self.reservation, self.decrementer_pending);
+
+        self.journal = Some(Vec::new());
+        let (executed, break_data) = self.call_compiled(code, generation);
+        let journal = self.journal.take().unwrap_or_default();
+        let compiled = (
+            executed,
+            break_data,
+            self.integer_state(),
+            self.written(&journal),
+        );
+        for (location, content) in journal.iter().rev() {
+            self.write_location(*location, content);
+        }
+        self.register = register;
+        self.time = time;
+        self.reservation = reservation;
+        self.decrementer_pending = decrementer_pending;
+        self.update_bat_tables();
+
+        // the compiled code already removed the block if it overwrote it, so the interpreter
+        // is stopped after the same number of instructions instead
+        self.journal = Some(Vec::new());
+        let mut interpreted_executed = executed;
+        let mut interpreted_break_data = BreakData::None;
+        for (index, instruction) in instructions[..executed].iter().enumerate() {
+            let address = self.register.pc;
+            let result = self.execute(*instruction);
+            let generation = self.block_cache.generation();
+            if let Some(break_data) = self.finish_instruction(result, address, generation) {
+                interpreted_executed = index + 1;
+                interpreted_break_data = break_data;
+                break;
+            };
+        }
+        let journal = self.journal.take().unwrap_or_default();
+        let interpreted = (
+            interpreted_executed,
+            interpreted_break_data,
+            self.integer_state(),
+            self.written(&journal),
+        );
+        assert_eq!(
+            compiled, interpreted,
+            "the compiled code at 0x{:x} doesn't behave like the interpreter",
+            pc
+        );
+        (interpreted.0, interpreted.1)
+    }
+
+    fn call_compiled(&mut self, code: CompiledCode, generation: u64) -> (usize, BreakData) {
+        if let Some(jit) = &mut self.jit {
+            jit.generation = generation;
+        };
+        let pc = self.register.pc;
+        // the code was compiled for M, and only access the registers and the helpers
+        let executed = unsafe { (code.function)(self as *mut Self as *mut u8, pc) };
+        let break_data = match &mut self.jit {
+            Some(jit) => replace(&mut jit.break_data, BreakData::None),
+            None => BreakData::None,
+        };
+        (executed as usize, break_data)
+    }
+
+    // the end of an instruction run by a helper, like in interpret_block
+    fn finish_compiled(&mut self, result: Result<BreakData, Exception>, pc: u32) -> u64 {
+        let generation = self.jit.as_ref().map_or(0, |jit| jit.generation);
+        match self.finish_instruction(result, pc, generation) {
+            Some(break_data) => {
+                if let Some(jit) = &mut self.jit {
+                    jit.break_data = break_data;
+                };
+                STOP
+            }
+            None => 0,
+        }
+    }
+
+    fn integer_state(&self) -> IntegerState {
+        let register = &self.register;
+        (
+            *register.gpr(),
+            [
+                register.pc,
+                register.xer,
+                register.lr,
+                register.ctr,
+                register.msr,
+                register.srr0,
+                register.srr1,
+                register.dar,
+                register.dsisr,
+                self.decrementer_pending as u32,
+            ],
+            register.cr,
+            self.reservation,
+        )
+    }
+
+    /// remember the size bytes at location before they are written, while the compiled code is
+    /// compared with the interpreter. The hardware registers aren't.
+    #[inline]
+    pub(crate) fn journal_write(&mut self, location: Location, size: usize) {
+        if self.journal.is_none() {
+            return;
+        };
+        if let Some(content) = self.read_location(location, size) {
+            if let Some(journal) = &mut self.journal {
+                journal.push((location, content));
+            };
+        };
+    }
+
+    // the locations written, with their content now
+    fn written(&self, journal: &[(Location, Vec<u8>)]) -> Vec<(Location, Vec<u8>)> {
+        journal
+            .iter()
+            .filter_map(|(location, content)| {
+                Some((*location, self.read_location(*location, content.len())?))
+            })
+            .collect()
+    }
+
+    fn read_location(&self, location: Location, size: usize) -> Option<Vec<u8>> {
+        match location {
+            Location::Physical(physical) => {
+                let mut content = vec![0; size];
+                self.memory.read_bytes(physical, &mut content).ok()?;
+                Some(content)
+            }
+            Location::LockedCache(offset) => self
+                .locked_cache
+                .get(offset..offset + size)
+                .map(<[u8]>::to_vec),
+        }
+    }
+
+    fn write_location(&mut self, location: Location, content: &[u8]) {
+        match location {
+            Location::Physical(physical) => {
+                // it was read from the memory
+                let _ = self.memory.write_bytes(physical, content);
+            }
+            Location::LockedCache(offset) => {
+                self.locked_cache[offset..offset + content.len()].copy_from_slice(content)
+            }
+        }
+    }
+}
+
+#[test]
+#[allow(clippy::unusual_byte_groupings)]
+fn test_jit_code_limit() {
+    use crate::{BASE_RW_ADRESS, OPCODE_BREAK};
+    // a block overwritten and compiled again and again
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    assert!(gekko.enable_jit());
+    gekko.jit.as_mut().unwrap().code_limit = 1;
+    gekko.write_u32(BASE_RW_ADRESS + 4, OPCODE_BREAK);
+    let mut code_sizes = Vec::new();
+    for _ in 0..10 {
+        gekko.write_u32(BASE_RW_ADRESS, 0b001110_00011_00011_00000000_00000001); // addi r3, r3, 1
+        gekko.register.pc = BASE_RW_ADRESS;
+        assert_eq!(gekko.run_until_event(), BreakData::Break);
+        code_sizes.push(gekko.jit.as_ref().unwrap().code_size);
+    }
+    assert_eq!(gekko.register.get_gpr(3), 10);
+    // only the last version of the block is kept
+    assert!(code_sizes.iter().all(|size| *size == code_sizes[0]));
+}
@@ -1,20 +1,70 @@
 mod interpreter;
-pub use interpreter::{BreakData, GekkoInterpreter};
+pub use interpreter::{BreakData, GekkoInterpreter, TrapKind};
+
+mod exception;
+pub use exception::{Exception, ExceptionKind, ProgramReason};
 
 mod register;
-pub use register::{GekkoRegister, Spr, Tbr};
+pub use register::{GekkoRegister, Spr, Tbr, GEKKO_PVR};
+pub use register::{HID2_LCE, HID2_LSQE, HID2_PSE, HID2_WPE};
+pub use register::{
+    MSR_BE, MSR_DR, MSR_EE, MSR_FE0, MSR_FE1, MSR_FP, MSR_ILE, MSR_IP, MSR_IR, MSR_LE, MSR_ME,
+    MSR_PM, MSR_POW, MSR_PR, MSR_RI, MSR_SE,
+};
+
+mod memory;
+pub use memory::{GuestMemory, MemoryFault, MemoryMap, SnapshotMemory, SparseMemory};
+pub use memory::{EXTENDED_RAM_ADDRESS, HARDWARE_ADDRESS, HARDWARE_SIZE, MEM1_SIZE, PAGE_SIZE};
+
+mod bulk_memory;
+pub use bulk_memory::BigEndian;
+
+mod heap;
+pub use heap::{GuestHeap, HeapStats, HEAP_ALIGNMENT};
+
+mod host_data;
+pub use host_data::{HostData, HostDataError, HostKey};
+
+mod mmio;
+pub use mmio::{MmioAccess, MmioBus, MmioDevice};
+
+mod mmu;
+pub use mmu::{AccessKind, BATU_VP, BATU_VS, SR_KP, SR_KS, SR_N, SR_T, SR_VSID};
+
+mod time;
+pub use time::{TimeBase, BUS_CLOCK, GEKKO_CLOCK};
+
+mod scheduler;
+pub use scheduler::{EventCallback, EventId, Scheduler};
 
 mod instruction;
 pub use instruction::Instruction;
 
+mod instruction_cache;
+
+mod block;
+
+#[cfg(feature = "jit")]
+mod jit;
+
 pub mod util;
 
-#[allow(clippy::inconsistent_digit_grouping)]
+#[allow(clippy::inconsistent_digit_grouping, clippy::unusual_byte_groupings)]
 pub const OPCODE_BREAK: u32 = 0b111011_00_00000000_00000000_00000000;
 
 pub const BASE_RW_ADRESS: u32 = 0x80000000;
 
+/// where the locked half of the L1 data cache is mapped when HID2[LCE] is set
+pub const LOCKED_CACHE_ADRESS: u32 = 0xE0000000;
+pub const LOCKED_CACHE_SIZE: usize = 16 * 1024;
+
 #[cfg(test)]
+// the opcodes written by the tests are grouped by field, not by nibble
+#[allow(
+    clippy::unusual_byte_groupings,
+    clippy::bool_assert_comparison,
+    unused_mut
+)]
 mod tests {
     mod test_interpreter;
 
@@ -1,5 +1,5 @@
-use gekko_interpreter::GekkoInterpreter;
 use gekko_interpreter::OPCODE_BREAK;
+use gekko_interpreter::{GekkoInterpreter, MemoryMap};
 use std::fs::File;
 use std::io::Read;
 
@@ -36,19 +36,10 @@ pub fn main() {
         actual_offset += size;
     }
 
-    let mut gekko = GekkoInterpreter::new(4);
-    gekko.replace_memory(vec);
+    vec.resize(gekko_interpreter::MEM1_SIZE, 0);
+    let mut gekko = GekkoInterpreter::with_memory(MemoryMap::from_buffer(vec));
 
     let instruction_to_blr = [
-        0x8029ee88, //OSDisableInterrupts
-        0x8029eeb0, //OSRestoreInterrupts
-        0x80299980, //PPCMtmmcr0
-        0x80299988, //PPCMtmmcr1
-        0x80299990, //PPCMtpmc1
-        0x80299998, //PPCMtpmc2
-        0x802999a0, //PPCMtpmc3
-        0x802999a8, //PPCMtpmc4
-        0x80299a10, //PPCDisableSpeculation
         0x80299a38, //PPCSetFpNonIEEEMode
         0x80299cb8, //OSInit, tmp
     ];
@@ -57,18 +48,6 @@ pub fn main() {
         gekko.write_u32(*offset, 0x4e800020);
     }
 
-    let instruction_to_nop = [
-        0x8029b760, //dataCacheBlockFlush
-        0x8029a220, //sync
-        0x8029b7b8, //icbi
-        0x8029b7c4, //sync
-        0x8029b7c8, //isync
-    ];
-
-    for offset in instruction_to_nop.iter() {
-        gekko.write_u32(*offset, 0x60000000);
-    }
-
     if false {
         gekko.register.set_gpr(1, 0x805a5420);
         gekko.register.set_gpr(13, 0x8058edc0);
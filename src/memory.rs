@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// size of the main memory of the GameCube
+pub const MEM1_SIZE: usize = 24 * 1024 * 1024;
+/// physical address of the additional memory of the development units, right after MEM1
+pub const EXTENDED_RAM_ADDRESS: u32 = 0x0180_0000;
+/// physical address of the hardware registers, seen at 0xCC00_0000 through the uncached mirror.
+/// They are handled by the devices of the MmioBus.
+pub const HARDWARE_ADDRESS: u32 = 0x0C00_0000;
+pub const HARDWARE_SIZE: usize = 0x1_0000;
+
+/// An access to the physical memory that couldn't be performed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MemoryFault {
+    // no region contains the size bytes at address
+    Unmapped { address: u32, size: u32 },
+}
+
+/// The physical memory the interpreter run on. Only read_bytes, write_bytes and clear need to be
+/// implemented, the values being stored in big endian.
+pub trait GuestMemory {
+    /// fill buffer with the bytes at address, which must all be backed
+    fn read_bytes(&self, address: u32, buffer: &mut [u8]) -> Result<(), MemoryFault>;
+
+    /// write data at address, which must all be backed
+    fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), MemoryFault>;
+
+    /// set every byte to 0
+    fn clear(&mut self);
+
+    fn read_u8(&self, address: u32) -> Result<u8, MemoryFault> {
+        let mut buffer = [0; 1];
+        self.read_bytes(address, &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn read_u16(&self, address: u32) -> Result<u16, MemoryFault> {
+        let mut buffer = [0; 2];
+        self.read_bytes(address, &mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    fn read_u32(&self, address: u32) -> Result<u32, MemoryFault> {
+        let mut buffer = [0; 4];
+        self.read_bytes(address, &mut buffer)?;
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    fn read_u64(&self, address: u32) -> Result<u64, MemoryFault> {
+        let mut buffer = [0; 8];
+        self.read_bytes(address, &mut buffer)?;
+        Ok(u64::from_be_bytes(buffer))
+    }
+
+    fn write_u8(&mut self, address: u32, value: u8) -> Result<(), MemoryFault> {
+        self.write_bytes(address, &[value])
+    }
+
+    fn write_u16(&mut self, address: u32, value: u16) -> Result<(), MemoryFault> {
+        self.write_bytes(address, &value.to_be_bytes())
+    }
+
+    fn write_u32(&mut self, address: u32, value: u32) -> Result<(), MemoryFault> {
+        self.write_bytes(address, &value.to_be_bytes())
+    }
+
+    fn write_u64(&mut self, address: u32, value: u64) -> Result<(), MemoryFault> {
+        self.write_bytes(address, &value.to_be_bytes())
+    }
+}
+
+enum Region {
+    Mem1,
+    ExtendedRam,
+}
+
+/// The physical memory of the console, as flat buffers. MEM1 is at 0, and is usually accessed
+/// through its cached (0x8000_0000) and uncached (0xC000_0000) mirrors set up by the BATs. The
+/// buffer of MEM1 can be anything seen as a slice, like a memory mapped file. The hardware
+/// registers are handled by the MmioBus of the interpreter.
+pub struct MemoryMap<B = Vec<u8>> {
+    pub mem1: B,
+    // empty if there is no extended ram
+    pub extended_ram: Vec<u8>,
+}
+
+impl Default for MemoryMap {
+    /// the memory of a retail GameCube
+    fn default() -> Self {
+        MemoryMap::new(MEM1_SIZE)
+    }
+}
+
+impl MemoryMap {
+    /// a memory map with mem1_size bytes of MEM1, and no extended ram
+    pub fn new(mem1_size: usize) -> MemoryMap {
+        MemoryMap::from_buffer(vec![0; mem1_size])
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> MemoryMap<B> {
+    /// a memory map using mem1 as MEM1, and no extended ram
+    pub fn from_buffer(mem1: B) -> MemoryMap<B> {
+        MemoryMap {
+            mem1,
+            extended_ram: Vec::new(),
+        }
+    }
+
+    /// add size bytes of ram at EXTENDED_RAM_ADDRESS, like the development units
+    pub fn with_extended_ram(mut self, size: usize) -> MemoryMap<B> {
+        assert!(self.mem1.as_ref().len() <= EXTENDED_RAM_ADDRESS as usize);
+        self.extended_ram = vec![0; size];
+        self
+    }
+
+    /// return the region containing address, and the offset of address in it
+    #[inline]
+    fn find_region(&self, address: u32) -> Option<(Region, usize)> {
+        let in_region = |start: u32, size: usize| {
+            address
+                .checked_sub(start)
+                .filter(|offset| (*offset as usize) < size)
+                .map(|offset| offset as usize)
+        };
+        if let Some(offset) = in_region(0, self.mem1.as_ref().len()) {
+            Some((Region::Mem1, offset))
+        } else {
+            in_region(EXTENDED_RAM_ADDRESS, self.extended_ram.len())
+                .map(|offset| (Region::ExtendedRam, offset))
+        }
+    }
+
+    /// the size bytes of physical memory at address. They must all be in the same region.
+    #[inline]
+    pub fn get(&self, address: u32, size: u32) -> Result<&[u8], MemoryFault> {
+        let fault = MemoryFault::Unmapped { address, size };
+        let (region, offset) = self.find_region(address).ok_or(fault)?;
+        let region = match region {
+            Region::Mem1 => self.mem1.as_ref(),
+            Region::ExtendedRam => &self.extended_ram,
+        };
+        region.get(offset..offset + size as usize).ok_or(fault)
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, address: u32, size: u32) -> Result<&mut [u8], MemoryFault> {
+        let fault = MemoryFault::Unmapped { address, size };
+        let (region, offset) = self.find_region(address).ok_or(fault)?;
+        let region = match region {
+            Region::Mem1 => self.mem1.as_mut(),
+            Region::ExtendedRam => &mut self.extended_ram,
+        };
+        region.get_mut(offset..offset + size as usize).ok_or(fault)
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> GuestMemory for MemoryMap<B> {
+    #[inline]
+    fn read_bytes(&self, address: u32, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+        buffer.copy_from_slice(self.get(address, buffer.len() as u32)?);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), MemoryFault> {
+        self.get_mut(address, data.len() as u32)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.mem1.as_mut().fill(0);
+        self.extended_ram.fill(0);
+    }
+}
+
+pub(crate) const PAGE_SHIFT: u32 = 12;
+/// size of the pages of SparseMemory and SnapshotMemory. It is also the smallest unit of
+/// translation, so the caches of the interpreter and the bulk accesses work by page.
+pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+type Page = Box<[u8; PAGE_SIZE]>;
+
+/// split the size bytes at address in parts that are each in a single page, and call access with
+/// the address of each part and its range in the whole access, until it fails. The addresses
+/// wrap around at 4 GiB.
+pub(crate) fn try_for_each_page<E>(
+    address: u32,
+    size: usize,
+    mut access: impl FnMut(u32, Range<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut done = 0;
+    while done < size {
+        let current = address.wrapping_add(done as u32);
+        let length = (PAGE_SIZE - page_offset(current)).min(size - done);
+        access(current, done..done + length)?;
+        done += length;
+    }
+    Ok(())
+}
+
+/// like try_for_each_page, for an access that can't fail
+pub(crate) fn for_each_page(address: u32, size: usize, mut access: impl FnMut(u32, Range<usize>)) {
+    let _ = try_for_each_page::<()>(address, size, |address, range| {
+        access(address, range);
+        Ok(())
+    });
+}
+
+#[inline]
+pub(crate) fn page_number(address: u32) -> u32 {
+    address >> PAGE_SHIFT
+}
+
+#[inline]
+pub(crate) fn page_offset(address: u32) -> usize {
+    address as usize & (PAGE_SIZE - 1)
+}
+
+/// check that the size bytes at address are in a memory of memory_size bytes starting at 0
+fn check_range(address: u32, size: usize, memory_size: usize) -> Result<(), MemoryFault> {
+    if address as usize + size <= memory_size {
+        Ok(())
+    } else {
+        Err(MemoryFault::Unmapped {
+            address,
+            size: size as u32,
+        })
+    }
+}
+
+/// A memory whose pages are allocated when they are first written, and read as 0 before. Useful
+/// for tests that touch a few addresses far apart.
+pub struct SparseMemory {
+    size: usize,
+    pages: HashMap<u32, Page>,
+}
+
+impl SparseMemory {
+    /// a memory of size bytes starting at 0, without any allocated page
+    pub fn new(size: usize) -> SparseMemory {
+        SparseMemory {
+            size,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// the number of pages that have been written to
+    pub fn allocated_pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl GuestMemory for SparseMemory {
+    fn read_bytes(&self, address: u32, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+        check_range(address, buffer.len(), self.size)?;
+        for_each_page(address, buffer.len(), |address, range| {
+            let offset = page_offset(address);
+            match self.pages.get(&page_number(address)) {
+                Some(page) => {
+                    buffer[range.clone()].copy_from_slice(&page[offset..offset + range.len()])
+                }
+                None => buffer[range].fill(0),
+            }
+        });
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), MemoryFault> {
+        check_range(address, data.len(), self.size)?;
+        for_each_page(address, data.len(), |address, range| {
+            let offset = page_offset(address);
+            let page = self
+                .pages
+                .entry(page_number(address))
+                .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+            page[offset..offset + range.len()].copy_from_slice(&data[range]);
+        });
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+/// A copy-on-write view of a memory image, that can be shared by many snapshots: a page of the
+/// image is copied the first time it is written. Cloning a snapshot fork it. Useful for tools
+/// that run the same state many times.
+#[derive(Clone)]
+pub struct SnapshotMemory {
+    base: Arc<[u8]>,
+    // the pages that differ from the base
+    pages: HashMap<u32, Page>,
+}
+
+impl SnapshotMemory {
+    /// a snapshot of base, which is the memory starting at 0
+    pub fn new(base: Arc<[u8]>) -> SnapshotMemory {
+        SnapshotMemory {
+            base,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// the number of pages that have been copied from the base
+    pub fn modified_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// discard every write, going back to the base
+    pub fn reset(&mut self) {
+        self.pages.clear();
+    }
+
+    // the last page of the base may be shorter than PAGE_SIZE
+    fn base_page(&self, page: u32) -> &[u8] {
+        let start = page as usize * PAGE_SIZE;
+        &self.base[start..(start + PAGE_SIZE).min(self.base.len())]
+    }
+}
+
+impl GuestMemory for SnapshotMemory {
+    fn read_bytes(&self, address: u32, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+        check_range(address, buffer.len(), self.base.len())?;
+        for_each_page(address, buffer.len(), |address, range| {
+            let offset = page_offset(address);
+            let page = match self.pages.get(&page_number(address)) {
+                Some(page) => &page[..],
+                None => self.base_page(page_number(address)),
+            };
+            buffer[range.clone()].copy_from_slice(&page[offset..offset + range.len()]);
+        });
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), MemoryFault> {
+        check_range(address, data.len(), self.base.len())?;
+        for_each_page(address, data.len(), |address, range| {
+            let (page_number, offset) = (page_number(address), page_offset(address));
+            if !self.pages.contains_key(&page_number) {
+                let mut page = Box::new([0; PAGE_SIZE]);
+                let base = self.base_page(page_number);
+                page[..base.len()].copy_from_slice(base);
+                self.pages.insert(page_number, page);
+            };
+            let page = self.pages.get_mut(&page_number).unwrap();
+            page[offset..offset + range.len()].copy_from_slice(&data[range]);
+        });
+        Ok(())
+    }
+
+    /// the base is left as it is: every page is replaced by a zeroed copy
+    fn clear(&mut self) {
+        for page in 0..self.base.len().div_ceil(PAGE_SIZE) {
+            self.pages.insert(page as u32, Box::new([0; PAGE_SIZE]));
+        }
+    }
+}
+
+#[test]
+fn test_memory_map() {
+    let mut memory = MemoryMap::new(0x100).with_extended_ram(0x100);
+    memory
+        .get_mut(0xF0, 4)
+        .unwrap()
+        .copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(memory.get(0xF0, 4), Ok(&[1, 2, 3, 4][..]));
+    assert_eq!(
+        memory.get(0xFE, 4),
+        Err(MemoryFault::Unmapped {
+            address: 0xFE,
+            size: 4
+        })
+    );
+    memory.get_mut(EXTENDED_RAM_ADDRESS + 0x10, 1).unwrap()[0] = 5;
+    assert_eq!(memory.extended_ram[0x10], 5);
+    assert!(memory.get(HARDWARE_ADDRESS, 1).is_err());
+    assert!(memory.get(0x200, 1).is_err());
+    memory.clear();
+    assert_eq!(memory.mem1[0xF0], 0);
+}
+
+#[test]
+fn test_sparse_memory() {
+    let mut memory = SparseMemory::new(0x100_0000);
+    assert_eq!(memory.read_u32(0x80_0000), Ok(0));
+    // across two pages
+    memory.write_u64(0x1FFC, 0x0102_0304_0506_0708).unwrap();
+    assert_eq!(memory.read_u64(0x1FFC), Ok(0x0102_0304_0506_0708));
+    assert_eq!(memory.read_u16(0x2000), Ok(0x0506));
+    assert_eq!(memory.allocated_pages(), 2);
+    assert!(memory.write_u32(0xFF_FFFE, 0).is_err());
+    memory.clear();
+    assert_eq!(memory.read_u32(0x1FFC), Ok(0));
+}
+
+#[test]
+fn test_snapshot_memory() {
+    let base: Arc<[u8]> = vec![0xFF; 3 * PAGE_SIZE].into();
+    let mut memory = SnapshotMemory::new(base.clone());
+    memory.write_u32(0x1000, 0x1234_5678).unwrap();
+    let fork = memory.clone();
+    memory.write_u32(0x1004, 0).unwrap();
+    assert_eq!(memory.read_u64(0x1000), Ok(0x1234_5678_0000_0000));
+    assert_eq!(fork.read_u64(0x1000), Ok(0x1234_5678_FFFF_FFFF));
+    assert_eq!(memory.modified_pages(), 1);
+    assert_eq!(base[0x1000], 0xFF);
+    memory.reset();
+    assert_eq!(memory.read_u32(0x1000), Ok(0xFFFF_FFFF));
+    assert!(memory.read_u8(3 * PAGE_SIZE as u32).is_err());
+}
@@ -0,0 +1,200 @@
+use crate::{HARDWARE_ADDRESS, HARDWARE_SIZE};
+
+/// A hardware device whose registers are mapped in the physical address space, like the video
+/// interface or the DSP mailboxes. offset is relative to the start of the range the device was
+/// registered at. A read returning None or a write returning false is reported as unhandled.
+pub trait MmioDevice {
+    fn read_u8(&mut self, _offset: u32) -> Option<u8> {
+        None
+    }
+
+    fn read_u16(&mut self, _offset: u32) -> Option<u16> {
+        None
+    }
+
+    fn read_u32(&mut self, _offset: u32) -> Option<u32> {
+        None
+    }
+
+    fn write_u8(&mut self, _offset: u32, _value: u8) -> bool {
+        false
+    }
+
+    fn write_u16(&mut self, _offset: u32, _value: u16) -> bool {
+        false
+    }
+
+    fn write_u32(&mut self, _offset: u32, _value: u32) -> bool {
+        false
+    }
+}
+
+/// An access to a hardware register that no device handled. Unhandled reads return 0, and
+/// unhandled writes are ignored.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MmioAccess {
+    // physical address of the register
+    pub address: u32,
+    // 1, 2 or 4 bytes
+    pub size: u8,
+    // the value written, or None for a read
+    pub value: Option<u32>,
+}
+
+struct RegisteredDevice {
+    start: u32,
+    size: u32,
+    device: Box<dyn MmioDevice>,
+}
+
+/// The devices mapped in the physical address space. The accesses to the hardware registers
+/// window, or to a registered device, are routed here when they aren't to the memory.
+#[derive(Default)]
+pub struct MmioBus {
+    devices: Vec<RegisteredDevice>,
+    unhandled: Vec<MmioAccess>,
+}
+
+impl MmioBus {
+    /// map device at the size bytes starting at the physical address start
+    pub fn register(&mut self, start: u32, size: u32, device: Box<dyn MmioDevice>) {
+        self.devices.push(RegisteredDevice {
+            start,
+            size,
+            device,
+        });
+    }
+
+    /// true if the accesses to address are routed to the bus
+    fn is_mapped(&self, address: u32) -> bool {
+        address.wrapping_sub(HARDWARE_ADDRESS) < HARDWARE_SIZE as u32
+            || self
+                .devices
+                .iter()
+                .any(|device| address.wrapping_sub(device.start) < device.size)
+    }
+
+    fn find_device(&mut self, address: u32) -> Option<(&mut Box<dyn MmioDevice>, u32)> {
+        self.devices
+            .iter_mut()
+            .find(|device| address.wrapping_sub(device.start) < device.size)
+            .map(|device| (&mut device.device, address - device.start))
+    }
+
+    /// read buffer.len() bytes at address, one register after another. Return false if the
+    /// address isn't mapped on the bus.
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> bool {
+        if !self.is_mapped(address) {
+            return false;
+        };
+        let width = register_width(buffer.len());
+        for (i, chunk) in buffer.chunks_mut(width).enumerate() {
+            let address = address + (i * width) as u32;
+            let value = self
+                .find_device(address)
+                .and_then(|(device, offset)| match width {
+                    1 => device.read_u8(offset).map(|value| value as u32),
+                    2 => device.read_u16(offset).map(|value| value as u32),
+                    _ => device.read_u32(offset),
+                });
+            let value = value.unwrap_or_else(|| {
+                self.unhandled.push(MmioAccess {
+                    address,
+                    size: width as u8,
+                    value: None,
+                });
+                0
+            });
+            chunk.copy_from_slice(&value.to_be_bytes()[4 - width..]);
+        }
+        true
+    }
+
+    /// write data at address, one register after another. Return false if the address isn't
+    /// mapped on the bus.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> bool {
+        if !self.is_mapped(address) {
+            return false;
+        };
+        let width = register_width(data.len());
+        for (i, chunk) in data.chunks(width).enumerate() {
+            let address = address + (i * width) as u32;
+            let mut bytes = [0; 4];
+            bytes[4 - width..].copy_from_slice(chunk);
+            let value = u32::from_be_bytes(bytes);
+            let handled = match self.find_device(address) {
+                Some((device, offset)) => match width {
+                    1 => device.write_u8(offset, value as u8),
+                    2 => device.write_u16(offset, value as u16),
+                    _ => device.write_u32(offset, value),
+                },
+                None => false,
+            };
+            if !handled {
+                self.unhandled.push(MmioAccess {
+                    address,
+                    size: width as u8,
+                    value: Some(value),
+                });
+            };
+        }
+        true
+    }
+
+    /// true if there are unhandled accesses since the last call to take_unhandled
+    #[inline]
+    pub fn has_unhandled(&self) -> bool {
+        !self.unhandled.is_empty()
+    }
+
+    /// remove and return the unhandled accesses since the last call
+    pub fn take_unhandled(&mut self) -> Vec<MmioAccess> {
+        std::mem::take(&mut self.unhandled)
+    }
+}
+
+/// the width of the registers accessed by an access of size bytes: the larger accesses, like the
+/// 64 bits ones of floating point stores, are split in 32 bits ones
+fn register_width(size: usize) -> usize {
+    match size {
+        1 | 2 => size,
+        size if size & 3 == 0 => 4,
+        _ => 1,
+    }
+}
+
+#[test]
+fn test_mmio_bus() {
+    struct Register(u16);
+    impl MmioDevice for Register {
+        fn read_u16(&mut self, offset: u32) -> Option<u16> {
+            (offset == 2).then_some(self.0)
+        }
+
+        fn write_u16(&mut self, offset: u32, value: u16) -> bool {
+            self.0 = value;
+            offset == 2
+        }
+    }
+    let mut bus = MmioBus::default();
+    bus.register(HARDWARE_ADDRESS + 0x2000, 0x100, Box::new(Register(5)));
+    let mut buffer = [0; 2];
+    assert!(bus.read(HARDWARE_ADDRESS + 0x2002, &mut buffer));
+    assert_eq!(buffer, [0, 5]);
+    assert!(bus.write(HARDWARE_ADDRESS + 0x2002, &[1, 2]));
+    assert!(bus.read(HARDWARE_ADDRESS + 0x2002, &mut buffer));
+    assert_eq!(buffer, [1, 2]);
+    assert!(bus.take_unhandled().is_empty());
+    let mut buffer = [0xFF; 4];
+    assert!(bus.read(HARDWARE_ADDRESS + 0x3000, &mut buffer));
+    assert_eq!(buffer, [0; 4]);
+    assert!(!bus.read(0x0D00_0000, &mut buffer));
+    assert_eq!(
+        bus.take_unhandled(),
+        vec![MmioAccess {
+            address: HARDWARE_ADDRESS + 0x3000,
+            size: 4,
+            value: None
+        }]
+    );
+}
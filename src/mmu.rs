@@ -0,0 +1,276 @@
+use crate::register::{MSR_DR, MSR_IR, MSR_PR};
+use crate::GekkoRegister;
+use crate::GuestMemory;
+
+// bits of a segment register
+pub const SR_T: u32 = 0x8000_0000;
+pub const SR_KS: u32 = 0x4000_0000;
+pub const SR_KP: u32 = 0x2000_0000;
+pub const SR_N: u32 = 0x1000_0000;
+pub const SR_VSID: u32 = 0x00FF_FFFF;
+
+// bits of the upper and lower BAT registers
+pub const BATU_VS: u32 = 0x0000_0002;
+pub const BATU_VP: u32 = 0x0000_0001;
+const BAT_BLOCK_MASK: u32 = 0xFFFE_0000;
+
+// flags of an entry of the BAT tables, next to the physical address of the 128 KiB block
+const ENTRY_VALID: u32 = 0x1;
+const ENTRY_READ: u32 = 0x2;
+const ENTRY_WRITE: u32 = 0x4;
+
+// bits of the first word of a page table entry
+const PTE_V: u32 = 0x8000_0000;
+const PTE_H: u32 = 0x0000_0040;
+
+/// why an effective address couldn't be translated
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TranslationFault {
+    // no BAT or page table entry translate it
+    NotMapped,
+    // the BAT or the page protection forbid the access
+    Protection,
+}
+
+/// the kind of memory access being translated
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AccessKind {
+    Fetch,
+    Load,
+    Store,
+}
+
+/// The translation of every 128 KiB block of the effective address space by the instruction and
+/// data BATs, so that translating an address covered by a BAT, like the ones of the standard
+/// SDK mapping, is a single lookup. They should be updated when a BAT register is written.
+/// There is a table for the supervisor mode and one for the user mode, indexed by MSR[PR], as a
+/// BAT can be valid in only one of them.
+pub(crate) struct BatTables {
+    instruction: [Vec<u32>; 2],
+    data: [Vec<u32>; 2],
+}
+
+impl BatTables {
+    pub fn new(register: &GekkoRegister) -> BatTables {
+        let mut tables = BatTables {
+            instruction: [vec![0; 1 << 15], vec![0; 1 << 15]],
+            data: [vec![0; 1 << 15], vec![0; 1 << 15]],
+        };
+        tables.update(register);
+        tables
+    }
+
+    pub fn update(&mut self, register: &GekkoRegister) {
+        fill_bat_table(&mut self.instruction, &register.ibat_u, &register.ibat_l);
+        fill_bat_table(&mut self.data, &register.dbat_u, &register.dbat_l);
+    }
+
+    /// translate an effective address to a physical one. Without translation (MSR[IR] or
+    /// MSR[DR] is 0 depending of the access), it is the same. Otherwise, the BATs are used, and
+    /// then the page table. Return why if the address can't be accessed.
+    pub fn translate(
+        &self,
+        register: &GekkoRegister,
+        memory: &impl GuestMemory,
+        address: u32,
+        access: AccessKind,
+    ) -> Result<u32, TranslationFault> {
+        let (table, translate) = match access {
+            AccessKind::Fetch => (&self.instruction, MSR_IR),
+            AccessKind::Load | AccessKind::Store => (&self.data, MSR_DR),
+        };
+        if !register.get_msr_bit(translate) {
+            return Ok(address);
+        };
+        let entry = table[register.get_msr_bit(MSR_PR) as usize][(address >> 17) as usize];
+        if entry & ENTRY_VALID == 0 {
+            return translate_with_page_table(register, memory, address, access);
+        };
+        let allowed = match access {
+            AccessKind::Store => ENTRY_WRITE,
+            AccessKind::Fetch | AccessKind::Load => ENTRY_READ,
+        };
+        if entry & allowed == 0 {
+            return Err(TranslationFault::Protection);
+        };
+        Ok((entry & BAT_BLOCK_MASK) | (address & !BAT_BLOCK_MASK))
+    }
+}
+
+/// fill the entries of the blocks covered by each BAT, in the supervisor table if Vs is set and
+/// in the user one if Vp is set. The lower numbered BATs have priority.
+fn fill_bat_table(table: &mut [Vec<u32>; 2], upper: &[u32; 4], lower: &[u32; 4]) {
+    table.iter_mut().for_each(|mode| mode.fill(0));
+    for (&batu, &batl) in upper.iter().zip(lower.iter()).rev() {
+        // PP: no access, read only or read and write
+        let flags = ENTRY_VALID
+            | match batl & 0x3 {
+                0 => 0,
+                2 => ENTRY_READ | ENTRY_WRITE,
+                _ => ENTRY_READ,
+            };
+        // the block length, in 128 KiB blocks
+        let block_length = (batu >> 2) & 0x7FF;
+        let effective_block = (batu & BAT_BLOCK_MASK & !(block_length << 17)) >> 17;
+        let physical_block = (batl & BAT_BLOCK_MASK & !(block_length << 17)) >> 17;
+        for (mode, valid) in table.iter_mut().zip([BATU_VS, BATU_VP]) {
+            if batu & valid == 0 {
+                continue;
+            };
+            for block in 0..=block_length {
+                mode[(effective_block | block) as usize] = ((physical_block | block) << 17) | flags;
+            }
+        }
+    }
+}
+
+/// translate address through its segment register and the hashed page table pointed by SDR1.
+/// Return the physical address, NotMapped if the segment is a direct-store or no-execute one or
+/// if there is no matching page table entry, or Protection if the page protection forbid the
+/// access.
+/// memory is the physical memory the page table is read from. The referenced and changed bits
+/// of the entries aren't updated.
+pub fn translate_with_page_table(
+    register: &GekkoRegister,
+    memory: &impl GuestMemory,
+    address: u32,
+    access: AccessKind,
+) -> Result<u32, TranslationFault> {
+    let segment = register.sr[(address >> 28) as usize];
+    if segment & SR_T != 0 || (access == AccessKind::Fetch && segment & SR_N != 0) {
+        return Err(TranslationFault::NotMapped);
+    };
+    let vsid = segment & SR_VSID;
+    let page_index = (address >> 12) & 0xFFFF;
+    let api = page_index >> 10;
+    let primary_hash = (vsid & 0x7_FFFF) ^ page_index;
+    for (hash, secondary) in [(primary_hash, false), (!primary_hash, true)] {
+        let htab_origin = register.sdr1 & 0xFFFF_0000;
+        let htab_mask = register.sdr1 & 0x1FF;
+        let pteg_address = (htab_origin & 0xFE00_0000)
+            | ((((htab_origin >> 16) & 0x1FF) | ((hash >> 10) & htab_mask)) << 16)
+            | ((hash & 0x3FF) << 6);
+        for entry in 0..8 {
+            let entry_address = pteg_address + entry * 8;
+            let word0 = memory
+                .read_u32(entry_address)
+                .map_err(|_| TranslationFault::NotMapped)?;
+            let word1 = memory
+                .read_u32(entry_address + 4)
+                .map_err(|_| TranslationFault::NotMapped)?;
+            if word0 & PTE_V == 0
+                || (word0 >> 7) & SR_VSID != vsid
+                || (word0 & PTE_H != 0) != secondary
+                || word0 & 0x3F != api
+            {
+                continue;
+            };
+            if !check_page_protection(register, segment, word1 & 0x3, access) {
+                return Err(TranslationFault::Protection);
+            };
+            return Ok((word1 & 0xFFFF_F000) | (address & 0xFFF));
+        }
+    }
+    Err(TranslationFault::NotMapped)
+}
+
+/// check the PP bits of a page table entry, with the key selected by MSR[PR]
+fn check_page_protection(
+    register: &GekkoRegister,
+    segment: u32,
+    pp: u32,
+    access: AccessKind,
+) -> bool {
+    let key = if register.get_msr_bit(MSR_PR) {
+        segment & SR_KP != 0
+    } else {
+        segment & SR_KS != 0
+    };
+    match (key, pp) {
+        (false, 0..=2) | (true, 2) => true,
+        (true, 0) => false,
+        // read only
+        _ => access != AccessKind::Store,
+    }
+}
+
+#[cfg(test)]
+use crate::SparseMemory;
+
+#[test]
+fn test_bat_tables() {
+    let mut register = GekkoRegister::default();
+    register.ibat_u = [0; 4];
+    register.ibat_l = [0; 4];
+    // 0x8000_0000 to 0x8FFF_FFFF, and read only 0x9000_0000 to 0x9001_FFFF to 0x0100_0000
+    register.dbat_u = [0x8000_1FFF, 0x9000_0002, 0, 0];
+    register.dbat_l = [0x0000_0002, 0x0100_0001, 0, 0];
+    let tables = BatTables::new(&register);
+    let memory = SparseMemory::new(0);
+    let translate = |address, access| tables.translate(&register, &memory, address, access);
+    assert_eq!(translate(0x8123_4567, AccessKind::Store), Ok(0x0123_4567));
+    assert_eq!(translate(0x9001_0000, AccessKind::Load), Ok(0x0101_0000));
+    assert_eq!(
+        translate(0x9001_0000, AccessKind::Store),
+        Err(TranslationFault::Protection)
+    );
+    assert_eq!(
+        translate(0x9002_0000, AccessKind::Load),
+        Err(TranslationFault::NotMapped)
+    );
+    assert_eq!(
+        translate(0x8000_0000, AccessKind::Fetch),
+        Err(TranslationFault::NotMapped)
+    );
+    register.msr &= !MSR_IR;
+    assert_eq!(
+        tables.translate(&register, &memory, 0x8000_0000, AccessKind::Fetch),
+        Ok(0x8000_0000)
+    );
+
+    // a supervisor only BAT doesn't hide a user only one for the same block
+    register.dbat_u = [0xA000_0002, 0xA000_0001, 0, 0];
+    register.dbat_l = [0x0000_0002, 0x0100_0002, 0, 0];
+    let tables = BatTables::new(&register);
+    assert_eq!(
+        tables.translate(&register, &memory, 0xA000_0010, AccessKind::Load),
+        Ok(0x0000_0010)
+    );
+    register.msr |= MSR_PR;
+    assert_eq!(
+        tables.translate(&register, &memory, 0xA000_0010, AccessKind::Load),
+        Ok(0x0100_0010)
+    );
+}
+
+#[test]
+fn test_page_table() {
+    let mut register = GekkoRegister::default();
+    let mut memory = SparseMemory::new(0x2_0000);
+    // a page table of 64 KiB at 0x10000
+    register.sdr1 = 0x0001_0000;
+    register.sr[1] = 0x0000_0123;
+    // map 0x1000_5000 to 0x3000, in the primary group
+    let hash = (0x123 ^ 0x0005) & 0x3FF;
+    let pte_address = 0x1_0000 + (hash << 6) + 8;
+    memory.write_u32(pte_address, PTE_V | (0x123 << 7)).unwrap();
+    memory.write_u32(pte_address + 4, 0x0000_3003).unwrap();
+    assert_eq!(
+        translate_with_page_table(&register, &memory, 0x1000_5678, AccessKind::Load),
+        Ok(0x3678)
+    );
+    // read only
+    assert_eq!(
+        translate_with_page_table(&register, &memory, 0x1000_5678, AccessKind::Store),
+        Err(TranslationFault::Protection)
+    );
+    assert_eq!(
+        translate_with_page_table(&register, &memory, 0x1000_6000, AccessKind::Load),
+        Err(TranslationFault::NotMapped)
+    );
+    register.sr[1] |= SR_N;
+    assert_eq!(
+        translate_with_page_table(&register, &memory, 0x1000_5678, AccessKind::Fetch),
+        Err(TranslationFault::NotMapped)
+    );
+}
@@ -1,6 +1,47 @@
 use crate::util::u16_get_section;
 use crate::BASE_RW_ADRESS;
 
+// bits of the floating point status and control register
+pub const FPSCR_FX: u32 = 0x8000_0000;
+pub const FPSCR_FEX: u32 = 0x4000_0000;
+pub const FPSCR_VX: u32 = 0x2000_0000;
+pub const FPSCR_VXSNAN: u32 = 0x0100_0000;
+pub const FPSCR_VXVC: u32 = 0x0008_0000;
+pub const FPSCR_FPCC: u32 = 0x0000_F000;
+pub const FPSCR_VE: u32 = 0x0000_0080;
+// every invalid operation exception bit, summarized by VX
+const FPSCR_VX_ANY: u32 = 0x01F8_0700;
+
+// bits of the machine state register
+pub const MSR_POW: u32 = 0x0004_0000;
+pub const MSR_ILE: u32 = 0x0001_0000;
+pub const MSR_EE: u32 = 0x0000_8000;
+pub const MSR_PR: u32 = 0x0000_4000;
+pub const MSR_FP: u32 = 0x0000_2000;
+pub const MSR_ME: u32 = 0x0000_1000;
+pub const MSR_FE0: u32 = 0x0000_0800;
+pub const MSR_SE: u32 = 0x0000_0400;
+pub const MSR_BE: u32 = 0x0000_0200;
+pub const MSR_FE1: u32 = 0x0000_0100;
+pub const MSR_IP: u32 = 0x0000_0040;
+pub const MSR_IR: u32 = 0x0000_0020;
+pub const MSR_DR: u32 = 0x0000_0010;
+pub const MSR_PM: u32 = 0x0000_0004;
+pub const MSR_RI: u32 = 0x0000_0002;
+pub const MSR_LE: u32 = 0x0000_0001;
+// bits of SRR1 copied back to the MSR by rfi
+pub const MSR_RFI_MASK: u32 = 0x87C0_FFFF;
+
+// bits of the hardware implementation register 2
+pub const HID2_LSQE: u32 = 0x8000_0000;
+pub const HID2_WPE: u32 = 0x4000_0000;
+pub const HID2_PSE: u32 = 0x2000_0000;
+pub const HID2_LCE: u32 = 0x1000_0000;
+
+/// value of the processor version register of the Gekko
+pub const GEKKO_PVR: u32 = 0x0008_3214;
+
+#[derive(Clone)]
 pub struct GekkoRegister {
     // general purpose register
     gpr: [u32; 32],
@@ -23,8 +64,61 @@ pub struct GekkoRegister {
 
     pub ctr: u32,
 
+    // floating point status and control register
+    pub fpscr: u32,
+
+    // machine state register
+    pub msr: u32,
+
     // graphical quantification register
     pub qr: [u32; 8],
+
+    // registers used by exceptions: save/restore registers, the data address and the cause of DSI
+    pub srr0: u32,
+    pub srr1: u32,
+    pub dar: u32,
+    pub dsisr: u32,
+
+    // registers reserved for the operating system
+    pub sprg: [u32; 4],
+
+    // segment registers, selected by the upper 4 bits of an effective address
+    pub sr: [u32; 16],
+
+    // page table base address, and external access register
+    pub sdr1: u32,
+    pub ear: u32,
+
+    // block address translation registers, as upper and lower half
+    pub ibat_u: [u32; 4],
+    pub ibat_l: [u32; 4],
+    pub dbat_u: [u32; 4],
+    pub dbat_l: [u32; 4],
+
+    // hardware implementation dependent registers
+    pub hid0: u32,
+    pub hid1: u32,
+    pub hid2: u32,
+
+    // write gather pipe address, and locked cache DMA address
+    pub wpar: u32,
+    pub dma_u: u32,
+    pub dma_l: u32,
+
+    // performance monitor. pmc[0] is PMC1
+    pub mmcr0: u32,
+    pub mmcr1: u32,
+    pub pmc: [u32; 4],
+    pub sia: u32,
+
+    // instruction and data address breakpoints
+    pub iabr: u32,
+    pub dabr: u32,
+
+    // L2 cache control, instruction cache throttling control and thermal management
+    pub l2cr: u32,
+    pub ictc: u32,
+    pub thrm: [u32; 3],
 }
 
 impl Default for GekkoRegister {
@@ -37,12 +131,48 @@ impl Default for GekkoRegister {
             xer: 0,
             cr: [0; 8],
             ctr: 0,
+            fpscr: 0,
+            // state left by the IPL: floating point and address translation enabled
+            msr: MSR_FP | MSR_IR | MSR_DR | MSR_RI,
             qr: [0; 8],
+            srr0: 0,
+            srr1: 0,
+            dar: 0,
+            dsisr: 0,
+            sprg: [0; 4],
+            sr: [0; 16],
+            sdr1: 0,
+            ear: 0,
+            // the mapping set up by the IPL: the cached and uncached 256 MiB mirrors of the
+            // physical memory at 0x8000_0000 and 0xC000_0000
+            ibat_u: [0x8000_1FFF, 0xC000_1FFF, 0, 0],
+            ibat_l: [0x0000_0002, 0x0000_002A, 0, 0],
+            dbat_u: [0x8000_1FFF, 0xC000_1FFF, 0, 0],
+            dbat_l: [0x0000_0002, 0x0000_002A, 0, 0],
+            hid0: 0,
+            hid1: 0,
+            hid2: 0,
+            wpar: 0,
+            dma_u: 0,
+            dma_l: 0,
+            mmcr0: 0,
+            mmcr1: 0,
+            pmc: [0; 4],
+            sia: 0,
+            iabr: 0,
+            dabr: 0,
+            l2cr: 0,
+            ictc: 0,
+            thrm: [0; 3],
         }
     }
 }
 
 impl GekkoRegister {
+    /// where the general purpose registers are, for the code compiled by the JIT
+    #[cfg(feature = "jit")]
+    pub(crate) const GPR_OFFSET: usize = std::mem::offset_of!(GekkoRegister, gpr);
+
     #[inline]
     pub fn get_gpr(&self, nb: u8) -> u32 {
         //println!("read 0x{:x} from gpr {}", self.gpr[nb as usize], nb);
@@ -56,6 +186,14 @@ impl GekkoRegister {
         self.gpr[nb as usize] = data;
     }
 
+    /// the general purpose registers, for the comparison of the compiled code with the
+    /// interpreter
+    #[cfg(feature = "jit")]
+    #[inline]
+    pub(crate) fn gpr(&self) -> &[u32; 32] {
+        &self.gpr
+    }
+
     #[inline]
     pub fn set_fpr_ps0(&mut self, nb: u8, value: f64) {
         println!("set ps0 of fpr {} to {}", nb, value);
@@ -96,6 +234,17 @@ impl GekkoRegister {
         (self.xer >> 31) != 0
     }
 
+    /// number of bytes transferred by lswx and stswx
+    #[inline]
+    pub fn get_xer_byte_count(&self) -> u8 {
+        (self.xer & 0x7F) as u8
+    }
+
+    #[inline]
+    pub fn set_xer_byte_count(&mut self, value: u8) {
+        self.xer = (self.xer & !0x7F) | (value as u32 & 0x7F);
+    }
+
     #[inline]
     pub fn update_cr0(&mut self, value: u32) {
         let value = value as i32;
@@ -108,14 +257,56 @@ impl GekkoRegister {
         } | (self.get_xer_so() as u8);
     }
 
+    /// copy the FX, FEX, VX and OX bits of the FPSCR into cr1
+    #[inline]
+    pub fn update_cr1(&mut self) {
+        self.cr[1] = (self.fpscr >> 28) as u8;
+    }
+
     #[inline]
     pub fn update_cr1_f64(&mut self, _value: f64) {
-        todo!("update_cr1 is not yet implemented");
+        self.update_cr1();
     }
 
     #[inline]
     pub fn update_cr1_f32(&mut self, _value: f32) {
-        todo!("update_cr1 is not yet implemented");
+        self.update_cr1();
+    }
+
+    /// set the FPCC field of the FPSCR. Same layout as a cr field (FL, FG, FE, FU)
+    #[inline]
+    pub fn set_fpscr_fpcc(&mut self, fpcc: u8) {
+        self.fpscr = (self.fpscr & !FPSCR_FPCC) | (((fpcc & 0xF) as u32) << 12);
+    }
+
+    /// raise some exception bits of the FPSCR, and update the FX, VX and FEX summary bits
+    #[inline]
+    pub fn set_fpscr_exception(&mut self, bits: u32) {
+        if self.fpscr & bits != bits {
+            self.fpscr |= FPSCR_FX;
+        };
+        self.fpscr |= bits;
+        if self.fpscr & FPSCR_VX_ANY != 0 {
+            self.fpscr |= FPSCR_VX;
+        };
+        // VX, OX, UX, ZX and XE each have their enable bit in VE, OE, UE, ZE and XE
+        if (self.fpscr >> 25) & (self.fpscr >> 3) & 0x1F != 0 {
+            self.fpscr |= FPSCR_FEX;
+        };
+    }
+
+    #[inline]
+    pub fn get_msr_bit(&self, bit: u32) -> bool {
+        self.msr & bit != 0
+    }
+
+    #[inline]
+    pub fn set_msr_bit(&mut self, bit: u32, value: bool) {
+        if value {
+            self.msr |= bit;
+        } else {
+            self.msr &= !bit;
+        }
     }
 
     #[inline]
@@ -150,20 +341,92 @@ impl GekkoRegister {
         }) + (d as i64)) as u32
     }
 
+    /// get the special purpose registers stored here. The time registers are handled by
+    /// GekkoInterpreter::get_spr, the public way to read any of them.
     #[inline]
-    pub fn get_spr(&self, spr: Spr) -> u32 {
+    pub(crate) fn get_spr(&self, spr: Spr) -> u32 {
         match spr {
+            Spr::XER => self.xer,
             Spr::LR => self.lr,
-            spr => todo!("getting the spr {:?}", spr),
+            Spr::CTR => self.ctr,
+            Spr::GQR(id) => self.get_qr(id),
+            Spr::SRR0 => self.srr0,
+            Spr::SRR1 => self.srr1,
+            Spr::DAR => self.dar,
+            Spr::DSISR => self.dsisr,
+            Spr::SPRG(id) => self.sprg[id as usize],
+            Spr::DEC | Spr::TBL | Spr::TBU => {
+                panic!("the time registers are handled by GekkoInterpreter::get_spr")
+            }
+            Spr::SDR1 => self.sdr1,
+            Spr::EAR => self.ear,
+            Spr::PVR => GEKKO_PVR,
+            Spr::IBATU(id) => self.ibat_u[id as usize],
+            Spr::IBATL(id) => self.ibat_l[id as usize],
+            Spr::DBATU(id) => self.dbat_u[id as usize],
+            Spr::DBATL(id) => self.dbat_l[id as usize],
+            Spr::HID0 => self.hid0,
+            Spr::HID1 => self.hid1,
+            Spr::HID2 => self.hid2,
+            Spr::WPAR => self.wpar,
+            Spr::DMA_U => self.dma_u,
+            Spr::DMA_L => self.dma_l,
+            Spr::MMCR0 => self.mmcr0,
+            Spr::MMCR1 => self.mmcr1,
+            Spr::PMC(id) => self.pmc[id as usize],
+            Spr::SIA => self.sia,
+            Spr::UMMCR0 => self.mmcr0,
+            Spr::UMMCR1 => self.mmcr1,
+            Spr::UPMC(id) => self.pmc[id as usize],
+            Spr::USIA => self.sia,
+            Spr::IABR => self.iabr,
+            Spr::DABR => self.dabr,
+            Spr::L2CR => self.l2cr,
+            Spr::ICTC => self.ictc,
+            Spr::THRM(id) => self.thrm[id as usize],
         }
     }
 
+    /// set the special purpose registers stored here, see get_spr
     #[inline]
-    pub fn set_spr(&mut self, spr: Spr, value: u32) {
+    pub(crate) fn set_spr(&mut self, spr: Spr, value: u32) {
         match spr {
+            Spr::XER => self.xer = value,
             Spr::LR => self.lr = value,
             Spr::CTR => self.ctr = value,
-            spr => todo!("setting the spr {:?}", spr),
+            Spr::GQR(id) => self.set_qr(id, value),
+            Spr::SRR0 => self.srr0 = value,
+            Spr::SRR1 => self.srr1 = value,
+            Spr::DAR => self.dar = value,
+            Spr::DSISR => self.dsisr = value,
+            Spr::SPRG(id) => self.sprg[id as usize] = value,
+            Spr::DEC | Spr::TBL | Spr::TBU => {
+                panic!("the time registers are handled by GekkoInterpreter::set_spr")
+            }
+            Spr::SDR1 => self.sdr1 = value,
+            Spr::EAR => self.ear = value,
+            Spr::PVR | Spr::UMMCR0 | Spr::UMMCR1 | Spr::UPMC(_) | Spr::USIA => {
+                panic!("the read only registers are rejected by GekkoInterpreter::set_spr")
+            }
+            Spr::IBATU(id) => self.ibat_u[id as usize] = value,
+            Spr::IBATL(id) => self.ibat_l[id as usize] = value,
+            Spr::DBATU(id) => self.dbat_u[id as usize] = value,
+            Spr::DBATL(id) => self.dbat_l[id as usize] = value,
+            Spr::HID0 => self.hid0 = value,
+            Spr::HID1 => self.hid1 = value,
+            Spr::HID2 => self.hid2 = value,
+            Spr::WPAR => self.wpar = value,
+            Spr::DMA_U => self.dma_u = value,
+            Spr::DMA_L => self.dma_l = value,
+            Spr::MMCR0 => self.mmcr0 = value,
+            Spr::MMCR1 => self.mmcr1 = value,
+            Spr::PMC(id) => self.pmc[id as usize] = value,
+            Spr::SIA => self.sia = value,
+            Spr::IABR => self.iabr = value,
+            Spr::DABR => self.dabr = value,
+            Spr::L2CR => self.l2cr = value,
+            Spr::ICTC => self.ictc = value,
+            Spr::THRM(id) => self.thrm[id as usize] = value,
         }
     }
 
@@ -183,13 +446,47 @@ impl GekkoRegister {
         (self.xer & (1 << 29)) != 0
     }
 
+    /// get the raw value of the graphic quantization register GQR`id` (0 to 7)
     #[inline]
     pub fn get_qr(&self, id: u8) -> u32 {
         self.qr[id as usize]
     }
+
+    /// set the raw value of the graphic quantization register GQR`id` (0 to 7),
+    /// like `mtspr GQR<id>, rS` does
+    #[inline]
+    pub fn set_qr(&mut self, id: u8, value: u32) {
+        self.qr[id as usize] = value;
+    }
+
+    /// configure the graphic quantization register GQR`id` (0 to 7), used by the psq_* instructions.
+    ///
+    /// the types are 0 for float, 4 for u8, 5 for u16, 6 for s8 and 7 for s16.
+    /// The scales are in the range -32..=31. Loaded values are divided by 2^load_scale,
+    /// and stored values are multiplied by 2^store_scale (scale are ignored for float).
+    pub fn configure_qr(
+        &mut self,
+        id: u8,
+        load_type: u8,
+        load_scale: i8,
+        store_type: u8,
+        store_scale: i8,
+    ) {
+        debug_assert!(load_type == 0 || (4..=7).contains(&load_type));
+        debug_assert!(store_type == 0 || (4..=7).contains(&store_type));
+        debug_assert!((-32..=31).contains(&load_scale));
+        debug_assert!((-32..=31).contains(&store_scale));
+        self.set_qr(
+            id,
+            (((load_scale as u32) & 0x3F) << 24)
+                | (((load_type as u32) & 0x7) << 16)
+                | (((store_scale as u32) & 0x3F) << 8)
+                | ((store_type as u32) & 0x7),
+        );
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Tbr {
     Tbl,
     Tbu,
@@ -197,32 +494,153 @@ pub enum Tbr {
 
 impl Tbr {
     #[inline]
-    pub fn decode_from_mftb(data: u16) -> Tbr {
+    pub fn decode_from_mftb(data: u16) -> Option<Tbr> {
         debug_assert_eq!(u16_get_section(data, 16 - 5, 5), 0b01000);
         match data >> 5 {
-            0b01100 => Tbr::Tbl,
-            0b01101 => Tbr::Tbu,
-            _ => panic!(),
+            0b01100 => Some(Tbr::Tbl),
+            0b01101 => Some(Tbr::Tbu),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
 pub enum Spr {
     XER,
     LR,
     CTR,
+    GQR(u8),
+    SRR0,
+    SRR1,
+    DAR,
+    DSISR,
+    SPRG(u8),
+    DEC,
+    TBL,
+    TBU,
+    SDR1,
+    EAR,
+    PVR,
+    IBATU(u8),
+    IBATL(u8),
+    DBATU(u8),
+    DBATL(u8),
+    HID0,
+    HID1,
+    HID2,
+    WPAR,
+    DMA_U,
+    DMA_L,
+    MMCR0,
+    MMCR1,
+    PMC(u8), // 0 for PMC1
+    SIA,
+    // the read only copies of the performance monitor registers, accessible from user mode
+    UMMCR0,
+    UMMCR1,
+    UPMC(u8), // 0 for UPMC1
+    USIA,
+    IABR,
+    DABR,
+    L2CR,
+    ICTC,
+    THRM(u8), // 0 for THRM1
 }
 
 impl Spr {
+    /// decode the spr field of mfspr/mtspr. The two 5 bits halves of the SPR number are swapped.
     #[inline]
-    pub fn decode_from_mfspr(data: u16) -> Spr {
-        debug_assert_eq!(data << (16 - 5), 0);
-        match data >> 5 {
-            0b00001 => Spr::XER,
-            0b01000 => Spr::LR,
-            0b01001 => Spr::CTR,
-            _ => panic!("unknown SPR for mfspr"),
-        }
+    pub fn decode_from_mfspr(data: u16) -> Option<Spr> {
+        let number = ((data & 0x1F) << 5) | (data >> 5);
+        Some(match number {
+            1 => Spr::XER,
+            8 => Spr::LR,
+            9 => Spr::CTR,
+            18 => Spr::DSISR,
+            19 => Spr::DAR,
+            22 => Spr::DEC,
+            25 => Spr::SDR1,
+            26 => Spr::SRR0,
+            27 => Spr::SRR1,
+            272..=275 => Spr::SPRG((number - 272) as u8),
+            282 => Spr::EAR,
+            284 => Spr::TBL,
+            285 => Spr::TBU,
+            287 => Spr::PVR,
+            528..=535 if number & 1 == 0 => Spr::IBATU(((number - 528) / 2) as u8),
+            528..=535 => Spr::IBATL(((number - 528) / 2) as u8),
+            536..=543 if number & 1 == 0 => Spr::DBATU(((number - 536) / 2) as u8),
+            536..=543 => Spr::DBATL(((number - 536) / 2) as u8),
+            912..=919 => Spr::GQR((number - 912) as u8),
+            920 => Spr::HID2,
+            921 => Spr::WPAR,
+            922 => Spr::DMA_U,
+            923 => Spr::DMA_L,
+            // the user level performance monitor registers, read only copy of the supervisor ones
+            936 => Spr::UMMCR0,
+            937 => Spr::UPMC(0),
+            938 => Spr::UPMC(1),
+            939 => Spr::USIA,
+            940 => Spr::UMMCR1,
+            941 => Spr::UPMC(2),
+            942 => Spr::UPMC(3),
+            952 => Spr::MMCR0,
+            953 => Spr::PMC(0),
+            954 => Spr::PMC(1),
+            955 => Spr::SIA,
+            956 => Spr::MMCR1,
+            957 => Spr::PMC(2),
+            958 => Spr::PMC(3),
+            1008 => Spr::HID0,
+            1009 => Spr::HID1,
+            1010 => Spr::IABR,
+            1013 => Spr::DABR,
+            1017 => Spr::L2CR,
+            1019 => Spr::ICTC,
+            1020..=1022 => Spr::THRM((number - 1020) as u8),
+            _ => return None,
+        })
+    }
+
+    /// decode the spr field of mtspr: like decode_from_mfspr, but the read only registers are
+    /// an invalid form
+    #[inline]
+    pub fn decode_from_mtspr(data: u16) -> Option<Spr> {
+        Spr::decode_from_mfspr(data).filter(|spr| !spr.is_read_only())
     }
+
+    /// true for the registers that mtspr can't write: PVR and the user level performance
+    /// monitor registers
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Spr::PVR | Spr::UMMCR0 | Spr::UMMCR1 | Spr::UPMC(_) | Spr::USIA
+        )
+    }
+
+    /// true if this register can only be accessed when MSR[PR] is 0
+    pub fn is_supervisor(&self) -> bool {
+        !matches!(
+            self,
+            Spr::XER | Spr::LR | Spr::CTR | Spr::UMMCR0 | Spr::UMMCR1 | Spr::UPMC(_) | Spr::USIA
+        )
+    }
+}
+
+#[test]
+fn test_decode_spr() {
+    // the two halves of the spr number are swapped in the instruction
+    assert_eq!(Spr::decode_from_mfspr(0b01000_00000), Some(Spr::LR));
+    assert_eq!(Spr::decode_from_mfspr(0b11010_00000), Some(Spr::SRR0));
+    assert_eq!(Spr::decode_from_mfspr(0b10001_10000), Some(Spr::IBATL(0)));
+    assert_eq!(Spr::decode_from_mfspr(0b11110_10000), Some(Spr::DBATU(3)));
+    assert_eq!(Spr::decode_from_mfspr(0b11011_11100), Some(Spr::DMA_L));
+    assert_eq!(Spr::decode_from_mfspr(0b10001_11111), Some(Spr::HID1));
+    assert_eq!(Spr::decode_from_mfspr(0b01000_11101), Some(Spr::UMMCR0));
+    assert_eq!(Spr::decode_from_mfspr(0b11000_11101), Some(Spr::MMCR0));
+    assert_eq!(Spr::decode_from_mfspr(0b00000_11111), None);
+    assert_eq!(Spr::decode_from_mtspr(0b01000_11101), None);
+    assert_eq!(Spr::decode_from_mtspr(0b11111_01000), None);
+    assert_eq!(Spr::decode_from_mtspr(0b11000_11101), Some(Spr::MMCR0));
 }
@@ -0,0 +1,85 @@
+use crate::{BreakData, GekkoInterpreter, MemoryMap};
+
+/// A callback called when the emulated time reach the cycle it was scheduled at. It may
+/// schedule new events (for example to be called periodically). If it return something else than
+/// BreakData::None, GekkoInterpreter::run_until_event stop and return it.
+pub type EventCallback<M = MemoryMap> = Box<dyn FnMut(&mut GekkoInterpreter<M>) -> BreakData>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EventId(u64);
+
+struct ScheduledEvent<M> {
+    cycle: u64,
+    id: EventId,
+    callback: EventCallback<M>,
+}
+
+/// A list of events that should happen at a given value of GekkoInterpreter::counter, like the
+/// vertical blank interrupt or the end of a DVD read.
+pub struct Scheduler<M = MemoryMap> {
+    // sorted by decreasing cycle, so the next event is the last one
+    events: Vec<ScheduledEvent<M>>,
+    next_id: u64,
+}
+
+// derive(Default) would require M: Default
+impl<M> Default for Scheduler<M> {
+    fn default() -> Self {
+        Scheduler {
+            events: Vec::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<M> Scheduler<M> {
+    /// schedule callback to be called when the counter reach cycle
+    pub fn schedule_at(&mut self, cycle: u64, callback: EventCallback<M>) -> EventId {
+        let id = EventId(self.next_id);
+        self.next_id += 1;
+        // events scheduled at the same cycle are called in the order they were scheduled
+        let position = self.events.partition_point(|event| event.cycle > cycle);
+        self.events.insert(
+            position,
+            ScheduledEvent {
+                cycle,
+                id,
+                callback,
+            },
+        );
+        id
+    }
+
+    /// remove an event that is still to come. Return false if it doesn't exist (anymore).
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        match self.events.iter().position(|event| event.id == id) {
+            Some(position) => {
+                self.events.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the cycle of the next event, if there is one
+    pub fn next_event_cycle(&self) -> Option<u64> {
+        self.events.last().map(|event| event.cycle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// remove and return the callback of the next event, if it is due at counter
+    pub fn pop_due_event(&mut self, counter: u64) -> Option<EventCallback<M>> {
+        if self.next_event_cycle()? <= counter {
+            self.events.pop().map(|event| event.callback)
+        } else {
+            None
+        }
+    }
+}
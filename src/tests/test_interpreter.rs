@@ -1,3 +1,5 @@
+#![allow(clippy::identity_op)]
+
 use crate::GekkoInterpreter;
 use crate::BASE_RW_ADRESS;
 use crate::OPCODE_BREAK;
@@ -291,3 +293,1215 @@ fn test_lmw() {
     assert_eq!(gekko.register.get_gpr(30), 0xDEAD_0000);
     assert_eq!(gekko.register.get_gpr(31), 0x0000_BEEF);
 }
+
+#[test]
+fn test_ps_merge() {
+    let mut gekko = GekkoInterpreter::new(4);
+    //test "ps_merge10 f1, f2, f3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_00001_00010_00011_1001010000_0);
+    gekko.register.set_fpr_ps0(2, 1.0);
+    gekko.register.set_fpr_ps1(2, 2.0);
+    gekko.register.set_fpr_ps0(3, 3.0);
+    gekko.register.set_fpr_ps1(3, 4.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(1), 2.0);
+    assert_eq!(gekko.register.get_fpr_ps1(1), 3.0);
+}
+
+#[test]
+fn test_ps_neg_abs() {
+    let mut gekko = GekkoInterpreter::new(8);
+    //test "ps_nabs f1, f2"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_00001_00000_00010_0010001000_0);
+    //test "ps_neg f3, f1"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b000100_00011_00000_00001_0000101000_0);
+    gekko.register.set_fpr_ps0(2, 1.5);
+    gekko.register.set_fpr_ps1(2, -2.5);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(1), -1.5);
+    assert_eq!(gekko.register.get_fpr_ps1(1), -2.5);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(3), 1.5);
+    assert_eq!(gekko.register.get_fpr_ps1(3), 2.5);
+}
+
+#[test]
+fn test_ps_sum() {
+    let mut gekko = GekkoInterpreter::new(8);
+    //test "ps_sum0 f1, f2, f4, f3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_00001_00010_00011_00100_01010_0);
+    //test "ps_sum1 f5, f2, f4, f3"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b000100_00101_00010_00011_00100_01011_0);
+    gekko.register.set_fpr_ps0(2, 1.0);
+    gekko.register.set_fpr_ps1(3, 10.0);
+    gekko.register.set_fpr_ps0(4, 7.0);
+    gekko.register.set_fpr_ps1(4, 8.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(1), 11.0);
+    assert_eq!(gekko.register.get_fpr_ps1(1), 8.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(5), 7.0);
+    assert_eq!(gekko.register.get_fpr_ps1(5), 11.0);
+}
+
+#[test]
+fn test_ps_sel() {
+    let mut gekko = GekkoInterpreter::new(4);
+    //test "ps_sel f1, f2, f4, f3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_00001_00010_00011_00100_10111_0);
+    gekko.register.set_fpr_ps0(2, 0.0);
+    gekko.register.set_fpr_ps1(2, f64::NAN);
+    gekko.register.set_fpr_ps0(3, 1.0);
+    gekko.register.set_fpr_ps1(3, 2.0);
+    gekko.register.set_fpr_ps0(4, 3.0);
+    gekko.register.set_fpr_ps1(4, 4.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(1), 3.0);
+    assert_eq!(gekko.register.get_fpr_ps1(1), 2.0);
+}
+
+#[test]
+fn test_ps_cmp() {
+    let mut gekko = GekkoInterpreter::new(8);
+    //test "ps_cmpu1 cr3, f2, f3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_01100_00010_00011_0001000000_0);
+    //test "ps_cmpo0 cr2, f2, f3"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b000100_01000_00010_00011_0000100000_0);
+    gekko.register.set_fpr_ps0(2, f64::NAN);
+    gekko.register.set_fpr_ps1(2, 1.0);
+    gekko.register.set_fpr_ps1(3, 2.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.cr[3], 0x8);
+    assert_eq!((gekko.register.fpscr >> 12) & 0xF, 0x8);
+    assert_eq!(gekko.register.fpscr & 0x8000_0000, 0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.cr[2], 0x1);
+    // quiet NaN in an ordered comparison set VXVC, VX and FX
+    assert_eq!(gekko.register.fpscr & 0xA008_0000, 0xA008_0000);
+}
+
+#[test]
+fn test_psq_st_quantized() {
+    let mut gekko = GekkoInterpreter::new(16);
+    // gqr1: store as u8, with a scale of 2
+    gekko.register.qr[1] = (2 << 8) | 4;
+    // gqr2: store as s16, with a scale of 0
+    gekko.register.qr[2] = 7;
+    //test "psq_st f1, 8(r3), 0, qr1"
+    gekko.write_u32(BASE_RW_ADRESS, 0b111100_00001_00011_0_001_000000001000);
+    //test "psq_st f1, 10(r3), 1, qr2"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b111100_00001_00011_1_010_000000001010);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS);
+    gekko.register.set_fpr_ps0(1, 3.0);
+    gekko.register.set_fpr_ps1(1, 300.0);
+    gekko.step().unwrap();
+    // 3.0 * 4 = 12, and 300.0 * 4 saturate to 255
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 8), 12);
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 9), 255);
+    gekko.register.set_fpr_ps0(1, -5.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.read_u16(BASE_RW_ADRESS + 10), (-5i16) as u16);
+    assert_eq!(gekko.read_u16(BASE_RW_ADRESS + 12), 0);
+}
+
+#[test]
+fn test_psq_l_quantized() {
+    let mut gekko = GekkoInterpreter::new(16);
+    // gqr3: load as s16, with a scale of 8
+    gekko.register.qr[3] = (8 << 24) | (7 << 16);
+    //test "psq_l f2, 8(r3), 0, qr3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b111000_00010_00011_0_011_000000001000);
+    //test "psq_l f4, 8(r3), 1, qr3"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b111000_00100_00011_1_011_000000001000);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0x0080_FF00);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(2), 0.5);
+    assert_eq!(gekko.register.get_fpr_ps1(2), -1.0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(4), 0.5);
+    assert_eq!(gekko.register.get_fpr_ps1(4), 1.0);
+}
+
+#[test]
+fn test_psq_l_reserved_type() {
+    let mut gekko = GekkoInterpreter::new(16);
+    // gqr3: load with the reserved type 1, read as a float without scaling
+    gekko.register.qr[3] = (8 << 24) | (1 << 16);
+    //test "psq_l f2, 8(r3), 0, qr3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b111000_00010_00011_0_011_000000001000);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 1.5f32.to_bits());
+    gekko.write_u32(BASE_RW_ADRESS + 12, (-2.0f32).to_bits());
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_fpr_ps0(2), 1.5);
+    assert_eq!(gekko.register.get_fpr_ps1(2), -2.0);
+}
+
+#[test]
+fn test_psq_lu_stu_loop() {
+    let mut gekko = GekkoInterpreter::new(64);
+    // gqr1: load u16 with a scale of 1, store as float
+    gekko.register.qr[1] = (1 << 24) | (5 << 16);
+    //loop: "psq_lu f1, 4(r3), 0, qr1"
+    gekko.write_u32(BASE_RW_ADRESS, 0b111001_00001_00011_0_001_000000000100);
+    //"psq_stu f1, 8(r4), 0, qr0"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b111101_00001_00100_0_000_000000001000);
+    //"bdnz loop"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b010000_10000_00000_11111111111110_0_0);
+    gekko.write_u32(BASE_RW_ADRESS + 12, OPCODE_BREAK);
+    for index in 0..8 {
+        gekko.write_u16(BASE_RW_ADRESS + 16 + index * 2, (index * 2) as u16);
+    }
+    gekko.register.set_gpr(3, BASE_RW_ADRESS + 16 - 4);
+    gekko.register.set_gpr(4, BASE_RW_ADRESS + 32 - 8);
+    gekko.register.ctr = 4;
+    gekko.run_until_event();
+    for index in 0..8 {
+        assert_eq!(
+            f32::from_bits(gekko.read_u32(BASE_RW_ADRESS + 32 + index * 4)),
+            index as f32
+        );
+    }
+    assert_eq!(gekko.register.get_gpr(3), BASE_RW_ADRESS + 16 + 12);
+    assert_eq!(gekko.register.get_gpr(4), BASE_RW_ADRESS + 32 + 24);
+}
+
+#[test]
+fn test_psq_lx_stux_loop() {
+    let mut gekko = GekkoInterpreter::new(64);
+    // gqr2: load as float, store s8 with a scale of 4
+    gekko.register.qr[2] = (4 << 8) | 6;
+    //loop: "psq_lx f2, r3, r5, 1, qr2"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_00010_00011_00101_1_010_000110_0);
+    //"psq_stux f2, r4, r6, 1, qr2"
+    gekko.write_u32(
+        BASE_RW_ADRESS + 4,
+        0b000100_00010_00100_00110_1_010_100111_0,
+    );
+    //"addi r5, r5, 4"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b001110_00101_00101_00000000_00000100);
+    //"bdnz loop"
+    gekko.write_u32(BASE_RW_ADRESS + 12, 0b010000_10000_00000_11111111111101_0_0);
+    gekko.write_u32(BASE_RW_ADRESS + 16, OPCODE_BREAK);
+    let values = [1.0f32, -0.5, 7.9, -10.0];
+    for (index, value) in values.iter().enumerate() {
+        gekko.write_u32(BASE_RW_ADRESS + 20 + index as u32 * 4, value.to_bits());
+    }
+    gekko.register.set_gpr(3, BASE_RW_ADRESS + 20);
+    gekko.register.set_gpr(4, BASE_RW_ADRESS + 40 - 1);
+    gekko.register.set_gpr(5, 0);
+    gekko.register.set_gpr(6, 1);
+    gekko.register.ctr = 4;
+    gekko.run_until_event();
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 40), 16);
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 41), (-8i8) as u8);
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 42), 126);
+    // saturated to the lowest s8
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 43), (-128i8) as u8);
+    assert_eq!(gekko.register.get_gpr(4), BASE_RW_ADRESS + 43);
+}
+
+#[test]
+fn test_mtspr_gqr() {
+    let mut gekko = GekkoInterpreter::new(8);
+    //test "mtspr GQR2, r3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_10010_11100_0111010011_0);
+    //test "mfspr r4, GQR2"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00100_10010_11100_0101010011_0);
+    gekko.register.set_gpr(3, 0x0007_0007);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_qr(2), 0x0007_0007);
+    gekko.register.configure_qr(2, 4, -1, 6, 3);
+    assert_eq!(gekko.register.get_qr(2), 0x3F04_0306);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(4), 0x3F04_0306);
+}
+
+#[test]
+fn test_mtspr_mfspr_supervisor() {
+    let mut gekko = GekkoInterpreter::new(12);
+    //test "mtspr HID2, r3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_11000_11100_0111010011_0);
+    //test "mfspr r4, SPRG1"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00100_10001_01000_0101010011_0);
+    //test "mtspr PVR, r3", an illegal instruction, then "mfspr r5, PVR"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_00011_11111_01000_0111010011_0);
+    gekko.register.set_gpr(3, 0xA000_0000);
+    gekko.register.sprg[1] = 42;
+    let msr = gekko.register.msr;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.hid2, 0xA000_0000);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(4), 42);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x700);
+    gekko.register.msr = msr;
+    gekko.register.pc = BASE_RW_ADRESS;
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00101_11111_01000_0101010011_0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(5), 0x0008_3214);
+}
+
+#[test]
+fn test_user_performance_monitor() {
+    use crate::{Exception, ProgramReason, Spr, MSR_PR};
+    let mut gekko = GekkoInterpreter::new(12);
+    //test "mtspr UMMCR0, r3", which is read only
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_01000_11101_0111010011_0);
+    //test "mfspr r4, UMMCR0", then "mfspr r5, MMCR0" in user mode
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00100_01000_11101_0101010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_00101_11000_11101_0101010011_0);
+    gekko.register.mmcr0 = 5;
+    gekko.register.set_gpr(3, 0xFF);
+    let msr = gekko.register.msr;
+    // the write is an illegal instruction
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.mmcr0, 5);
+    assert_eq!(gekko.register.pc, 0x700);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS);
+    assert_eq!(
+        gekko.set_spr(Spr::PVR, 0),
+        Err(Exception::Program(ProgramReason::IllegalInstruction))
+    );
+    gekko.register.msr = msr;
+    gekko.register.pc = BASE_RW_ADRESS + 4;
+    gekko.register.set_msr_bit(MSR_PR, true);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(4), 5);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x700);
+    assert_eq!(gekko.register.get_gpr(5), 0);
+}
+
+#[test]
+fn test_disable_interrupts() {
+    use crate::MSR_EE;
+    let mut gekko = GekkoInterpreter::new(16);
+    // the body of OSDisableInterrupts
+    //"mfmsr r3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_00000_00000_0001010011_0);
+    //"rlwinm r4, r3, 0, 17, 15"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b010101_00011_00100_00000_10001_01111_0);
+    //"mtmsr r4"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_00100_00000_00000_0010010010_0);
+    //"rlwinm r3, r3, 17, 31, 31"
+    gekko.write_u32(
+        BASE_RW_ADRESS + 12,
+        0b010101_00011_00011_10001_11111_11111_0,
+    );
+    gekko.register.set_msr_bit(MSR_EE, true);
+    let msr = gekko.register.msr;
+    for _ in 0..4 {
+        gekko.step().unwrap();
+    }
+    assert_eq!(gekko.register.msr, msr & !MSR_EE);
+    assert_eq!(gekko.register.get_gpr(3), 1);
+}
+
+#[test]
+fn test_rfi() {
+    use crate::{MSR_EE, MSR_PR};
+    let mut gekko = GekkoInterpreter::new(4);
+    //test "rfi"
+    gekko.write_u32(BASE_RW_ADRESS, 0b010011_00000_00000_00000_0000110010_0);
+    gekko.register.srr0 = 0x8000_1234;
+    gekko.register.srr1 = MSR_EE | MSR_PR;
+    // without translation, the code is at its physical address
+    gekko.register.msr = 0;
+    gekko.register.pc = 0;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x8000_1234);
+    assert_eq!(gekko.register.msr, MSR_EE | MSR_PR);
+}
+
+#[test]
+fn test_dsi_exception() {
+    use crate::MSR_EE;
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    //test "lwz r3, 0(r4)" with r4 out of the memory
+    gekko.write_u32(
+        BASE_RW_ADRESS + 0x100,
+        0b100000_00011_00100_00000000_00000000,
+    );
+    gekko.register.pc = BASE_RW_ADRESS + 0x100;
+    gekko.register.set_gpr(4, 0x7000_0000);
+    gekko.register.set_msr_bit(MSR_EE, true);
+    let msr = gekko.register.msr;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x300);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS + 0x100);
+    assert_eq!(gekko.register.srr1, msr);
+    assert_eq!(gekko.register.dar, 0x7000_0000);
+    assert_eq!(gekko.register.dsisr, 0x4000_0000);
+    assert_eq!(gekko.register.get_msr_bit(MSR_EE), false);
+}
+
+#[test]
+fn test_program_exception() {
+    use crate::{MSR_FP, MSR_PR};
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    // an illegal instruction
+    gekko.write_u32(BASE_RW_ADRESS, 0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x700);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS);
+    assert_eq!(gekko.register.srr1 & 0x0008_0000, 0x0008_0000);
+    // "mfmsr r3" in user mode
+    gekko.reboot();
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_00000_00000_0001010011_0);
+    gekko.register.set_msr_bit(MSR_PR, true);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x700);
+    assert_eq!(gekko.register.srr1 & 0x0004_0000, 0x0004_0000);
+    assert_eq!(gekko.register.get_msr_bit(MSR_PR), false);
+    // "lfs f1, 0(r3)" with the fpu disabled
+    gekko.reboot();
+    gekko.write_u32(BASE_RW_ADRESS, 0b110000_00001_00011_00000000_00000000);
+    gekko.register.set_msr_bit(MSR_FP, false);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x800);
+}
+
+#[test]
+fn test_external_interrupt() {
+    use crate::MSR_EE;
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    //"addi r3, r3, 1", then the handler return with "rfi"
+    gekko.write_u32(BASE_RW_ADRESS, 0b001110_00011_00011_00000000_00000001);
+    gekko.write_u32(
+        BASE_RW_ADRESS + 0x500,
+        0b010011_00000_00000_00000_0000110010_0,
+    );
+    gekko.external_interrupt = true;
+    // masked while MSR[EE] is 0
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 4);
+    gekko.register.pc = BASE_RW_ADRESS;
+    gekko.register.set_msr_bit(MSR_EE, true);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x500);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS);
+    assert_eq!(gekko.register.get_gpr(3), 1);
+    gekko.external_interrupt = false;
+    gekko.step().unwrap();
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 4);
+    assert_eq!(gekko.register.get_gpr(3), 2);
+}
+
+#[test]
+fn test_exception_as_break() {
+    use crate::{BreakData, Exception, ExceptionKind, ProgramReason};
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    gekko.write_u32(BASE_RW_ADRESS, 0);
+    gekko.break_on_exception.insert(ExceptionKind::Program);
+    assert_eq!(
+        gekko.run_until_event(),
+        BreakData::Exception(Exception::Program(ProgramReason::IllegalInstruction))
+    );
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS);
+    assert_eq!(gekko.register.srr0, 0);
+}
+
+#[test]
+fn test_timebase() {
+    let mut gekko = GekkoInterpreter::new(16);
+    //"mtspr TBU, r3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_11101_01000_0111010011_0);
+    //"mtspr TBL, r4"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00100_11100_01000_0111010011_0);
+    //"mftb r5, TBL"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_00101_01100_01000_0101110011_0);
+    //"mftb r6, TBU"
+    gekko.write_u32(BASE_RW_ADRESS + 12, 0b011111_00110_01101_01000_0101110011_0);
+    gekko.register.set_gpr(3, 0x12);
+    gekko.register.set_gpr(4, 0xFFFF_FFFF);
+    gekko.step().unwrap();
+    gekko.step().unwrap();
+    assert_eq!(gekko.get_timebase(), 0x12_FFFF_FFFF);
+    // 12 cpu cycles for a time base tick
+    gekko.counter += 12 - 2;
+    gekko.step().unwrap();
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(5), 0);
+    assert_eq!(gekko.register.get_gpr(6), 0x13);
+}
+
+#[test]
+fn test_decrementer_exception() {
+    use crate::MSR_EE;
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    //"mtspr DEC, r3"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_10110_00000_0111010011_0);
+    //"b 0" (infinite loop)
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b010010_00000000_00000000_00000000_0_0);
+    gekko.register.set_gpr(3, 2);
+    gekko.register.set_msr_bit(MSR_EE, true);
+    gekko.time.set_clock_ratio(gekko.counter, 1);
+    gekko.step().unwrap();
+    // the decrementer goes from 0 to -1 after 3 ticks of 4 cycles
+    while gekko.register.pc != 0x900 {
+        gekko.step().unwrap();
+        assert!(gekko.counter <= 12);
+    }
+    assert_eq!(gekko.counter, 12);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS + 4);
+    assert_eq!(gekko.get_spr(crate::Spr::DEC), 0xFFFF_FFFF);
+}
+
+#[test]
+fn test_scheduler() {
+    use crate::BreakData;
+    let mut gekko = GekkoInterpreter::new(8);
+    //"addi r3, r3, 1", then "b -4"
+    gekko.write_u32(BASE_RW_ADRESS, 0b001110_00011_00011_00000000_00000001);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b010010_11_11111111_11111111_111111_0_0);
+    // a periodic event every 10 cycles, that stop the third time
+    fn periodic(gekko: &mut GekkoInterpreter) -> BreakData {
+        gekko.register.set_gpr(4, gekko.register.get_gpr(4) + 1);
+        if gekko.register.get_gpr(4) == 3 {
+            BreakData::Break
+        } else {
+            gekko.schedule_in(10, Box::new(periodic));
+            BreakData::None
+        }
+    }
+    gekko.schedule_in(10, Box::new(periodic));
+    let cancelled = gekko.schedule_in(15, Box::new(|_| BreakData::Break));
+    assert!(gekko.scheduler.cancel(cancelled));
+    assert!(!gekko.scheduler.cancel(cancelled));
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.counter, 30);
+    assert_eq!(gekko.register.get_gpr(3), 15);
+    assert_eq!(gekko.register.get_gpr(4), 3);
+    assert!(gekko.scheduler.is_empty());
+}
+
+#[test]
+fn test_sc() {
+    use crate::BreakData;
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    //test "sc"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b010001_00000_00000_00000000000000_1_0);
+    gekko.register.pc = BASE_RW_ADRESS + 8;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0xC00);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS + 12);
+    gekko.register.msr = gekko.register.srr1;
+    gekko.register.pc = BASE_RW_ADRESS + 8;
+    gekko.break_on_system_call = true;
+    assert_eq!(gekko.step().unwrap(), BreakData::SystemCall);
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 12);
+}
+
+#[test]
+fn test_trap() {
+    use crate::{BreakData, TrapKind};
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    //test "twi 4, r3, 0" (trap if r3 == 0)
+    gekko.write_u32(BASE_RW_ADRESS, 0b000011_00100_00011_00000000_00000000);
+    //test "tw 0b01000, r3, r4" (trap if r3 > r4, signed)
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_01000_00011_00100_0000000100_0);
+    gekko.register.set_gpr(3, 1);
+    gekko.register.set_gpr(4, 0xFFFF_FFFF);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 4);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x700);
+    assert_eq!(gekko.register.srr0, BASE_RW_ADRESS + 4);
+    assert_eq!(gekko.register.srr1 & 0x0002_0000, 0x0002_0000);
+
+    gekko.reboot();
+    gekko.write_u32(BASE_RW_ADRESS, 0b000011_00100_00011_00000000_00000000);
+    gekko.break_on_trap = true;
+    assert_eq!(
+        gekko.step().unwrap(),
+        BreakData::Trap {
+            pc: BASE_RW_ADRESS,
+            kind: TrapKind::Twi {
+                to: 4,
+                gpr_a: 3,
+                simm: 0
+            }
+        }
+    );
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 4);
+}
+
+#[test]
+fn test_dcbz() {
+    let mut gekko = GekkoInterpreter::new(0x80);
+    //test "dcbz r3, r4"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00000_00011_00100_1111110110_0);
+    for offset in (0x20..0x80).step_by(4) {
+        gekko.write_u32(BASE_RW_ADRESS + offset, 0xFFFF_FFFF);
+    }
+    gekko.register.set_gpr(3, BASE_RW_ADRESS + 0x40);
+    gekko.register.set_gpr(4, 0x05);
+    gekko.step().unwrap();
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x3C), 0xFFFF_FFFF);
+    for offset in (0x40..0x60).step_by(4) {
+        assert_eq!(gekko.read_u32(BASE_RW_ADRESS + offset), 0);
+    }
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x60), 0xFFFF_FFFF);
+}
+
+#[test]
+fn test_cache_and_sync_as_nop() {
+    let mut gekko = GekkoInterpreter::new(24);
+    //"dcbf r3, r4", "sync", "icbi r3, r4", "isync", "eieio"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00000_00011_00100_0001010110_0);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0x7C0004AC);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_00000_00011_00100_1111010110_0);
+    gekko.write_u32(BASE_RW_ADRESS + 12, 0x4C00012C);
+    gekko.write_u32(BASE_RW_ADRESS + 16, 0x7C0006AC);
+    gekko.write_u32(BASE_RW_ADRESS + 20, OPCODE_BREAK);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS);
+    assert_eq!(gekko.run_until_event(), crate::BreakData::Break);
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 24);
+}
+
+#[test]
+fn test_locked_cache() {
+    use crate::{Exception, ExceptionKind, ProgramReason, HID2_LCE, LOCKED_CACHE_ADRESS};
+    let mut gekko = GekkoInterpreter::new(0x100);
+    //test "dcbz_l r3, r4"
+    gekko.write_u32(BASE_RW_ADRESS, 0b000100_00000_00011_00100_1111110110_0);
+    //test "mtspr DMA_U, r5", "mtspr DMA_L, r6", "mtspr DMA_U, r7", "mtspr DMA_L, r8"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00101_11010_11100_0111010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_00110_11011_11100_0111010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 12, 0b011111_00111_11010_11100_0111010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 16, 0b011111_01000_11011_11100_0111010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 20, OPCODE_BREAK);
+    gekko.register.set_gpr(3, LOCKED_CACHE_ADRESS);
+    gekko.register.set_gpr(4, 0x25);
+
+    // the locked cache is disabled
+    gekko.break_on_exception.insert(ExceptionKind::Program);
+    assert_eq!(
+        gekko.try_read_u32(LOCKED_CACHE_ADRESS),
+        Err(Exception::Dsi {
+            address: LOCKED_CACHE_ADRESS,
+            store: false,
+            protection: false
+        })
+    );
+    assert_eq!(
+        gekko.step(),
+        Ok(crate::BreakData::Exception(Exception::Program(
+            ProgramReason::IllegalInstruction
+        )))
+    );
+
+    gekko.register.hid2 = HID2_LCE;
+    for offset in (0..0x60).step_by(4) {
+        gekko.write_u32(LOCKED_CACHE_ADRESS + offset, 0xFFFF_FFFF);
+    }
+    for offset in (0x40..0x80).step_by(4) {
+        gekko.write_u32(BASE_RW_ADRESS + offset, offset);
+    }
+    // load 2 blocks from 0x40 to the start of the locked cache
+    gekko.register.set_gpr(5, 0x0000_0040);
+    gekko
+        .register
+        .set_gpr(6, LOCKED_CACHE_ADRESS | 0x10 | (2 << 2) | 0x2);
+    // store 1 block from 0x20 in the locked cache to 0xC0
+    gekko.register.set_gpr(7, 0x0000_00C0);
+    gekko
+        .register
+        .set_gpr(8, (LOCKED_CACHE_ADRESS + 0x20) | (1 << 2) | 0x2);
+    gekko.step().unwrap();
+    for offset in (0x20..0x40).step_by(4) {
+        assert_eq!(gekko.read_u32(LOCKED_CACHE_ADRESS + offset), 0);
+    }
+    assert_eq!(gekko.read_u32(LOCKED_CACHE_ADRESS + 0x40), 0xFFFF_FFFF);
+    gekko.step().unwrap();
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.dma_l, LOCKED_CACHE_ADRESS | 0x10 | (2 << 2));
+    for offset in (0..0x40).step_by(4) {
+        assert_eq!(gekko.read_u32(LOCKED_CACHE_ADRESS + offset), 0x40 + offset);
+    }
+    assert_eq!(gekko.read_u32(LOCKED_CACHE_ADRESS + 0x40), 0xFFFF_FFFF);
+    assert_eq!(gekko.run_until_event(), crate::BreakData::Break);
+    for offset in (0..0x20).step_by(4) {
+        assert_eq!(
+            gekko.read_u32(BASE_RW_ADRESS + 0xC0 + offset),
+            0x60 + offset
+        );
+    }
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0xE0), 0);
+
+    // dcbz_l outside of the locked cache doesn't touch the main memory
+    gekko.register.set_gpr(3, BASE_RW_ADRESS + 0xC0);
+    gekko.register.set_gpr(4, 0);
+    gekko.register.pc = BASE_RW_ADRESS;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 4);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0xC0), 0x60);
+}
+
+#[test]
+fn test_load_store_string() {
+    let mut gekko = GekkoInterpreter::new(0x40);
+    //test "lswi r30, r3, 10"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_11110_00011_01010_1001010101_0);
+    //test "stswx r30, r4, r5"
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_11110_00100_00101_1010010101_0);
+    //test "lswx r10, r3, r5"
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b011111_01010_00011_00101_1000010101_0);
+    gekko.write_u32(BASE_RW_ADRESS + 12, OPCODE_BREAK);
+    for offset in 0..12 {
+        gekko.write_u8(BASE_RW_ADRESS + 0x10 + offset, offset as u8 + 1);
+    }
+    gekko.register.set_gpr(0, 0xFFFF_FFFF);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS + 0x10);
+    gekko.register.set_gpr(4, BASE_RW_ADRESS + 0x20);
+    gekko.register.set_gpr(5, 0);
+    gekko.register.set_gpr(10, 0xFFFF_FFFF);
+    gekko.register.set_xer_byte_count(10);
+    assert_eq!(gekko.register.get_xer_byte_count(), 10);
+
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(30), 0x0102_0304);
+    assert_eq!(gekko.register.get_gpr(31), 0x0506_0708);
+    assert_eq!(gekko.register.get_gpr(0), 0x090A_0000);
+
+    gekko.step().unwrap();
+    for offset in 0..10 {
+        assert_eq!(
+            gekko.read_u8(BASE_RW_ADRESS + 0x20 + offset),
+            offset as u8 + 1
+        );
+    }
+    assert_eq!(gekko.read_u8(BASE_RW_ADRESS + 0x2A), 0);
+
+    // nothing is transferred when the byte count is 0
+    gekko.register.set_xer_byte_count(0);
+    assert_eq!(gekko.run_until_event(), crate::BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(10), 0xFFFF_FFFF);
+}
+
+#[test]
+fn test_load_store_string_wrap() {
+    use crate::Spr;
+    let mut gekko = GekkoInterpreter::new(0x40000);
+    //test "lswi r5, r3, 6", "stswi r5, r4, 6"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00101_00011_00110_1001010101_0);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00101_00100_00110_1011010101_0);
+    gekko.write_u32(BASE_RW_ADRESS + 8, OPCODE_BREAK);
+    // map the last block of the address space before the first one
+    gekko.set_spr(Spr::DBATU(2), 0xFFFE_0002).unwrap();
+    gekko.set_spr(Spr::DBATL(2), 0x0000_0002).unwrap();
+    gekko.set_spr(Spr::DBATU(3), 0x0000_0002).unwrap();
+    gekko.set_spr(Spr::DBATL(3), 0x0002_0002).unwrap();
+    gekko.write_u32(BASE_RW_ADRESS + 0x1FFFC, 0x0000_1122);
+    gekko.write_u32(BASE_RW_ADRESS + 0x20000, 0x3344_5566);
+    gekko.register.set_gpr(3, 0xFFFF_FFFE);
+    gekko.register.set_gpr(4, 0xFFFF_FFFF);
+    assert_eq!(gekko.run_until_event(), crate::BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(5), 0x1122_3344);
+    assert_eq!(gekko.register.get_gpr(6), 0x5566_0000);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x1FFFC), 0x0000_1111);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x20000), 0x2233_4455);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x20004), 0x6600_0000);
+}
+
+#[test]
+fn test_reservation() {
+    use crate::Exception;
+    let mut gekko = GekkoInterpreter::new(0x40);
+    //test "lwarx r5, 0, r3", "stwcx. r6, 0, r3", twice
+    for i in 0..2 {
+        gekko.write_u32(
+            BASE_RW_ADRESS + i * 8,
+            0b011111_00101_00000_00011_0000010100_0,
+        );
+        gekko.write_u32(
+            BASE_RW_ADRESS + i * 8 + 4,
+            0b011111_00110_00000_00011_0010010110_1,
+        );
+    }
+    gekko.write_u32(BASE_RW_ADRESS + 0x24, 1);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS + 0x24);
+    gekko.register.set_gpr(6, 2);
+
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.get_gpr(5), 1);
+    assert_eq!(gekko.reservation, Some(0x20));
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.cr[0], 0b0010);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x24), 2);
+    assert_eq!(gekko.reservation, None);
+
+    // a write from the embedder to the same block between the pair
+    gekko.register.set_gpr(6, 3);
+    gekko.step().unwrap();
+    gekko.write_u32(BASE_RW_ADRESS + 0x3C, 0);
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.cr[0], 0b0000);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x24), 2);
+
+    // an exception clear the reservation
+    gekko.register.pc = BASE_RW_ADRESS;
+    gekko.step().unwrap();
+    gekko.take_exception(Exception::External);
+    assert_eq!(gekko.reservation, None);
+}
+
+#[test]
+fn test_segment_register() {
+    let mut gekko = GekkoInterpreter::new(0x2_0000);
+    //test "mtsr 1, r3", "mfsrin r5, r4", "lwz r6, 0(r4)"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_0_0001_00000_0011010010_0);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00101_00000_00100_1010010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b100000_00110_00100_00000000_00000000);
+    gekko.write_u32(BASE_RW_ADRESS + 12, OPCODE_BREAK);
+    gekko.register.set_gpr(3, 0x0000_0123);
+    gekko.register.set_gpr(4, 0x1000_5678);
+    // a page table at 0x10000, mapping 0x1000_5000 to 0x3000
+    gekko.register.sdr1 = 0x0001_0000;
+    let pte_address = BASE_RW_ADRESS + 0x1_0000 + (((0x123 ^ 0x5) & 0x3FF) << 6);
+    gekko.write_u32(pte_address, 0x8000_0000 | (0x123 << 7));
+    gekko.write_u32(pte_address + 4, 0x0000_3002);
+    gekko.write_u32(BASE_RW_ADRESS + 0x3678, 0x1234_5678);
+    assert_eq!(gekko.run_until_event(), crate::BreakData::Break);
+    assert_eq!(gekko.register.sr[1], 0x0000_0123);
+    assert_eq!(gekko.register.get_gpr(5), 0x0000_0123);
+    assert_eq!(gekko.register.get_gpr(6), 0x1234_5678);
+}
+
+#[test]
+fn test_bat_translation() {
+    let mut gekko = GekkoInterpreter::new(0x1000);
+    //test "mtspr DBAT2U, r3", "mtspr DBAT2L, r4", "lwz r5, 0x40(r6)"
+    gekko.write_u32(BASE_RW_ADRESS, 0b011111_00011_11100_10000_0111010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00100_11101_10000_0111010011_0);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b100000_00101_00110_00000000_01000000);
+    gekko.write_u32(BASE_RW_ADRESS + 12, OPCODE_BREAK);
+    // map 0x4000_0000 to 0x4001_FFFF to the start of the memory
+    gekko.register.set_gpr(3, 0x4000_0002);
+    gekko.register.set_gpr(4, 0x0000_0002);
+    gekko.register.set_gpr(6, 0x4000_0000);
+    gekko.write_u32(BASE_RW_ADRESS + 0x40, 0x1234_5678);
+    // the uncached mirror
+    assert_eq!(gekko.read_u32(0xC000_0040), 0x1234_5678);
+    assert!(gekko.try_read_u32(0x4000_0040).is_err());
+    assert_eq!(gekko.run_until_event(), crate::BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(5), 0x1234_5678);
+    // without translation, physical addresses are used
+    gekko.register.msr = 0;
+    assert_eq!(gekko.read_u32(0x40), 0x1234_5678);
+    assert!(gekko.try_read_u32(BASE_RW_ADRESS + 0x40).is_err());
+}
+
+#[test]
+fn test_protection_dsisr() {
+    use crate::{Exception, Spr};
+    let mut gekko = GekkoInterpreter::new(0x2_0000);
+    //test "stw r5, 0(r6)", "stw r5, 0(r4)"
+    gekko.write_u32(BASE_RW_ADRESS, 0b100100_00101_00110_00000000_00000000);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b100100_00101_00100_00000000_00000000);
+    // map 0x4000_0000 to 0x4001_FFFF read only
+    gekko.set_spr(Spr::DBATU(2), 0x4000_0002).unwrap();
+    gekko.set_spr(Spr::DBATL(2), 0x0000_0001).unwrap();
+    gekko.register.set_gpr(6, 0x4000_0010);
+    // a read only page table entry mapping 0x1000_5000 to 0x3000
+    gekko.register.sdr1 = 0x0001_0000;
+    gekko.register.sr[1] = 0x0000_0123;
+    let pte_address = BASE_RW_ADRESS + 0x1_0000 + (((0x123 ^ 0x5) & 0x3FF) << 6);
+    gekko.write_u32(pte_address, 0x8000_0000 | (0x123 << 7));
+    gekko.write_u32(pte_address + 4, 0x0000_3003);
+    gekko.register.set_gpr(4, 0x1000_5678);
+    assert_eq!(
+        gekko.try_write_u32(0x4000_0010, 0),
+        Err(Exception::Dsi {
+            address: 0x4000_0010,
+            store: true,
+            protection: true
+        })
+    );
+    assert_eq!(gekko.read_u32(0x4000_0010), 0);
+
+    let msr = gekko.register.msr;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x300);
+    assert_eq!(gekko.register.dar, 0x4000_0010);
+    // the protection forbid the store
+    assert_eq!(gekko.register.dsisr, 0x0A00_0000);
+    gekko.register.msr = msr;
+    gekko.register.pc = BASE_RW_ADRESS + 4;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x300);
+    assert_eq!(gekko.register.dar, 0x1000_5678);
+    assert_eq!(gekko.register.dsisr, 0x0A00_0000);
+}
+
+#[test]
+fn test_memory_map() {
+    use crate::{Exception, MemoryMap, MEM1_SIZE};
+    let mut gekko = GekkoInterpreter::default();
+    assert_eq!(gekko.memory.mem1.len(), MEM1_SIZE);
+    gekko.write_u32(0xC000_0100, 0x5678_9ABC);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x100), 0x5678_9ABC);
+    assert_eq!(
+        gekko.try_read_u32(BASE_RW_ADRESS + MEM1_SIZE as u32),
+        Err(Exception::Dsi {
+            address: BASE_RW_ADRESS + MEM1_SIZE as u32,
+            store: false,
+            protection: false
+        })
+    );
+
+    let mut gekko =
+        GekkoInterpreter::with_memory(MemoryMap::new(MEM1_SIZE).with_extended_ram(0x100));
+    gekko.write_u32(BASE_RW_ADRESS + MEM1_SIZE as u32, 1);
+    assert_eq!(gekko.memory.extended_ram[3], 1);
+}
+
+#[test]
+fn test_mmio_device() {
+    use crate::{BreakData, MmioAccess, MmioDevice, HARDWARE_ADDRESS};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    // an interrupt cause register, cleared by writing 1 to its bits
+    struct InterruptCause(Rc<Cell<u32>>);
+    impl MmioDevice for InterruptCause {
+        fn read_u32(&mut self, offset: u32) -> Option<u32> {
+            (offset == 0).then(|| self.0.get())
+        }
+
+        fn write_u32(&mut self, offset: u32, value: u32) -> bool {
+            self.0.set(self.0.get() & !value);
+            offset == 0
+        }
+    }
+    let cause = Rc::new(Cell::new(0x0000_0103));
+    let mut gekko = GekkoInterpreter::new(0x100);
+    gekko.register_mmio_device(
+        HARDWARE_ADDRESS + 0x3000,
+        0x100,
+        Box::new(InterruptCause(cause.clone())),
+    );
+    //test "lwz r3, 0x3000(r4)", "stw r5, 0x3000(r4)", "lwz r6, 0x4000(r4)"
+    gekko.write_u32(BASE_RW_ADRESS, 0b100000_00011_00100_00110000_00000000);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b100100_00101_00100_00110000_00000000);
+    gekko.write_u32(BASE_RW_ADRESS + 8, 0b100000_00110_00100_01000000_00000000);
+    gekko.write_u32(BASE_RW_ADRESS + 12, OPCODE_BREAK);
+    gekko.register.set_gpr(4, 0xCC00_0000);
+    gekko.register.set_gpr(5, 0x0000_0100);
+    gekko.register.set_gpr(6, 0xFFFF_FFFF);
+    gekko.break_on_unhandled_mmio = true;
+    assert_eq!(
+        gekko.run_until_event(),
+        BreakData::UnhandledMmio(MmioAccess {
+            address: HARDWARE_ADDRESS + 0x4000,
+            size: 4,
+            value: None
+        })
+    );
+    assert_eq!(gekko.register.get_gpr(3), 0x0000_0103);
+    assert_eq!(cause.get(), 0x0000_0003);
+    assert_eq!(gekko.register.get_gpr(6), 0);
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+}
+
+#[test]
+fn test_guest_memory() {
+    use crate::{BreakData, GuestMemory, SnapshotMemory, SparseMemory, MEM1_SIZE};
+    use std::sync::Arc;
+    let mut gekko = GekkoInterpreter::with_memory(SparseMemory::new(MEM1_SIZE));
+    gekko.write_u32(BASE_RW_ADRESS, 0b11111_00010_00011_00100_0_100001010_0); // r2 = r3 + r4
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b100100_00010_00101_00000000_00000000); // stw r2, 0(r5)
+    gekko.write_u32(BASE_RW_ADRESS + 8, OPCODE_BREAK);
+    gekko.register.set_gpr(3, 10);
+    gekko.register.set_gpr(4, 15);
+    gekko.register.set_gpr(5, 0x8100_0000);
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.memory.read_u32(0x0100_0000), Ok(10 + 15));
+    assert_eq!(gekko.memory.allocated_pages(), 2);
+
+    // run the same code twice from a snapshot of the memory
+    let mut image = vec![0; 0x1000];
+    image[..4].copy_from_slice(&0b100100_00011_00101_00000000_00000000u32.to_be_bytes()); // stw r3, 0(r5)
+    image[4..8].copy_from_slice(&OPCODE_BREAK.to_be_bytes());
+    let base: Arc<[u8]> = image.into();
+    let mut gekko = GekkoInterpreter::with_memory(SnapshotMemory::new(base.clone()));
+    gekko.register.set_gpr(3, 1);
+    gekko.register.set_gpr(5, BASE_RW_ADRESS + 0x100);
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x100), 1);
+    let previous = gekko.replace_memory(SnapshotMemory::new(base));
+    assert_eq!(previous.modified_pages(), 1);
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x100), 0);
+}
+
+#[test]
+fn test_bulk_memory() {
+    use crate::Exception;
+    let mut gekko = GekkoInterpreter::new(0x4000);
+    let file: Vec<u8> = (0..0x1800).map(|i| i as u8).collect();
+    // across a page
+    gekko.write_bytes(BASE_RW_ADRESS + 0x800, &file).unwrap();
+    let mut buffer = vec![0; 0x1800];
+    gekko.read_bytes(0xC000_0800, &mut buffer).unwrap();
+    assert_eq!(buffer, file);
+    assert_eq!(
+        gekko.read_bytes(BASE_RW_ADRESS + 0x3000, &mut buffer),
+        Err(Exception::Dsi {
+            address: BASE_RW_ADRESS + 0x4000,
+            store: false,
+            protection: false
+        })
+    );
+
+    // overlapping copy
+    gekko
+        .copy(BASE_RW_ADRESS + 0x801, BASE_RW_ADRESS + 0x800, 4)
+        .unwrap();
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x800), 0x0000_0102);
+    gekko.fill(BASE_RW_ADRESS + 0xFFE, 0xAA, 4).unwrap();
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0xFFE), 0xAAAA_AAAA);
+
+    gekko
+        .write_cstring(BASE_RW_ADRESS + 0x10, b"main.dol")
+        .unwrap();
+    assert_eq!(
+        gekko.read_cstring(BASE_RW_ADRESS + 0x10, 0x100).unwrap(),
+        b"main.dol"
+    );
+    assert_eq!(
+        gekko.read_cstring(BASE_RW_ADRESS + 0x10, 4).unwrap(),
+        b"main"
+    );
+
+    gekko
+        .write_array(BASE_RW_ADRESS + 0x20, &[1.5f32, -2.0])
+        .unwrap();
+    assert_eq!(gekko.read_u32(BASE_RW_ADRESS + 0x20), 0x3FC0_0000);
+    let mut values = [0u16; 4];
+    gekko
+        .read_array(BASE_RW_ADRESS + 0x20, &mut values)
+        .unwrap();
+    assert_eq!(values, [0x3FC0, 0, 0xC000, 0]);
+}
+
+#[test]
+fn test_os_heap() {
+    use crate::HostKey;
+    let mut gekko = GekkoInterpreter::new(0x10_0000);
+    gekko.register.lr = BASE_RW_ADRESS + 0x100;
+    // OSCreateHeap(0x8008_0010, 0x8009_0000)
+    gekko.register.set_gpr(3, 0x8008_0010);
+    gekko.register.set_gpr(4, 0x8009_0000);
+    gekko.os_create_heap();
+    let handle = gekko.register.get_gpr(3);
+    assert_eq!(gekko.register.pc, BASE_RW_ADRESS + 0x100);
+    assert_eq!(gekko.heap_stats(handle as usize).unwrap().size, 0xFFE0);
+    // overlapping the first heap, outside of the memory, and empty
+    for (start, end) in [
+        (0x8008_8000, 0x8009_8000),
+        (0x800F_8000, 0x8010_8000),
+        (0x8009_0000, 0x8009_0010),
+    ] {
+        gekko.register.set_gpr(3, start);
+        gekko.register.set_gpr(4, end);
+        gekko.os_create_heap();
+        assert_eq!(gekko.register.get_gpr(3), 0xFFFF_FFFF);
+    }
+    assert_eq!(gekko.create_heap(0x8009_0000, 0x1000), Some(1));
+    gekko.register.set_gpr(3, handle);
+
+    gekko.register.set_gpr(4, 0x40);
+    gekko.os_alloc_from_heap();
+    let block = gekko.register.get_gpr(3);
+    assert_eq!(block, 0x8008_0020);
+    gekko.write_u32(block, 0x1234_5678);
+    gekko.host_data.insert(HostKey::Address(block), "texture");
+
+    gekko.register.set_gpr(3, handle);
+    gekko.register.set_gpr(4, 0x10_0000);
+    gekko.os_alloc_from_heap();
+    assert_eq!(gekko.register.get_gpr(3), 0);
+
+    gekko.register.set_gpr(3, handle);
+    gekko.register.set_gpr(4, block);
+    gekko.os_free_to_heap();
+    assert_eq!(gekko.host_data.get::<&str>(HostKey::Address(block)), None);
+    assert_eq!(gekko.heap_stats(handle as usize).unwrap().used, 0);
+    gekko.os_destroy_heap();
+    assert!(gekko.heap_stats(handle as usize).is_none());
+}
+
+#[test]
+fn test_instruction_cache() {
+    use crate::{BreakData, GuestMemory};
+    let addi_r3 = |simm: u32| 0b001110_00011_00011_00000000_00000000 | simm;
+    let mut gekko = GekkoInterpreter::new(0x100);
+    gekko.write_u32(BASE_RW_ADRESS, addi_r3(1));
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b100100_00100_00101_00000000_00000000); // stw r4, 0(r5)
+    gekko.write_u32(BASE_RW_ADRESS + 8, OPCODE_BREAK);
+    gekko.register.set_gpr(4, addi_r3(0x10));
+    gekko.register.set_gpr(5, BASE_RW_ADRESS);
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(3), 1);
+    // the code modified itself
+    gekko.register.pc = BASE_RW_ADRESS;
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(3), 0x11);
+
+    // a write that bypass the interpreter isn't seen until the cache is invalidated
+    gekko.memory.write_u32(8, addi_r3(0x100)).unwrap();
+    gekko.memory.write_u32(12, OPCODE_BREAK).unwrap();
+    gekko.register.pc = BASE_RW_ADRESS;
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(3), 0x21);
+    gekko.invalidate_instruction_cache(BASE_RW_ADRESS + 8);
+    gekko.register.pc = BASE_RW_ADRESS;
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert_eq!(gekko.register.get_gpr(3), 0x131);
+}
+
+#[test]
+fn test_basic_blocks() {
+    use crate::BreakData;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    // run a loop with an event in the middle, by blocks and by steps
+    let run = |use_blocks: bool| {
+        let mut gekko = GekkoInterpreter::new(0x100);
+        gekko.use_blocks = use_blocks;
+        gekko.write_u32(BASE_RW_ADRESS, 0b001110_00011_00011_00000000_00000001); // addi r3, r3, 1
+        gekko.write_u32(BASE_RW_ADRESS + 4, 0b001110_00100_00100_00000000_00000010); // addi r4, r4, 2
+        gekko.write_u32(BASE_RW_ADRESS + 8, 0b010000_10000_00000_11111111_11111000); // bdnz -8
+        gekko.write_u32(BASE_RW_ADRESS + 12, OPCODE_BREAK);
+        gekko.register.ctr = 100;
+        let seen = Rc::new(Cell::new((0, 0)));
+        let event_seen = seen.clone();
+        gekko.schedule_in(
+            50,
+            Box::new(move |gekko| {
+                event_seen.set((gekko.counter, gekko.register.get_gpr(3)));
+                BreakData::None
+            }),
+        );
+        assert_eq!(gekko.run_until_event(), BreakData::Break);
+        (gekko.counter, gekko.register.get_gpr(4), seen.get())
+    };
+    assert_eq!(run(true), (301, 200, (50, 17)));
+    assert_eq!(run(true), run(false));
+}
+
+#[test]
+fn test_log_trigger() {
+    use crate::BreakData;
+    // the logging start at 0x8000_3264, even in the middle of straight-line code
+    let mut gekko = GekkoInterpreter::new(0x4000);
+    gekko.write_u32(0x8000_3260, 0b001110_00011_00011_00000000_00000001); // addi r3, r3, 1
+    gekko.write_u32(0x8000_3264, 0b001110_00011_00011_00000000_00000001); // addi r3, r3, 1
+    gekko.write_u32(0x8000_3268, OPCODE_BREAK);
+    gekko.register.pc = 0x8000_3260;
+    assert_eq!(gekko.run_until_event(), BreakData::Break);
+    assert!(gekko.log);
+    assert_eq!(gekko.register.get_gpr(3), 2);
+}
+
+#[test]
+#[cfg(feature = "jit")]
+fn test_jit() {
+    use crate::{BreakData, Exception, ExceptionKind};
+    // a loop mixing compiled and interpreted instructions, then a call and a load raising a DSI,
+    // with the JIT in lockstep and without
+    let run = |jit: bool| {
+        let mut gekko = GekkoInterpreter::new(0x1000);
+        if jit {
+            assert!(gekko.enable_jit());
+            gekko.jit_lockstep = true;
+        };
+        let code = [
+            0b001111_00101_00000_10000000_00000000, // addis r5, 0, 0x8000
+            0b011000_00110_00110_00010010_00110100, // ori r6, r6, 0x1234
+            0b001110_00011_00011_00000000_00000001, // addi r3, r3, 1
+            0b011111_00111_00011_00110_0_100001010_1, // add. r7, r3, r6
+            0b001101_01110_00011_11111111_11111111, // addic. r14, r3, -1
+            0b010011_00110_00110_00110_0011000001_0, // crxor 6, 6, 6
+            0b100100_00111_00101_00000010_00000000, // stw r7, 0x200(r5)
+            0b100000_01111_00101_00000010_00000000, // lwz r15, 0x200(r5)
+            0b100010_10000_00101_00000010_00000011, // lbz r16, 0x203(r5)
+            0b101000_10001_00101_00000010_00000010, // lhz r17, 0x202(r5)
+            0b001110_10010_00101_00000000_00000000, // addi r18, r5, 0
+            0b100111_00011_10010_00000011_00000000, // stbu r3, 0x300(r18)
+            0b100001_10011_10010_11111111_00000000, // lwzu r19, -0x100(r18)
+            0b001011_00100_00011_00000000_00011001, // cmpwi cr1, r3, 25
+            0b011111_01000_00011_01110_0000100000_0, // cmplw cr2, r3, r14
+            0b011111_01000_00011_00111_0_000101000_1, // subf. r8, r3, r7
+            0b010101_00111_01001_00100_00000_11011_1, // rlwinm. r9, r7, 4, 0, 27
+            0b010100_00111_01100_01000_10000_10111_0, // rlwimi r12, r7, 8, 16, 23
+            0b010000_10000_00000_11111111_10111000, // bdnz -72
+            0b010010_00000000_00000000_00000011_0_1, // bl 12
+            0b001111_10101_00000_01110000_00000000, // addis r21, 0, 0x7000
+            0b100000_10100_10101_00000000_00000000, // lwz r20, 0(r21)
+            0b001110_10110_10110_00000000_00000111, // addi r22, r22, 7
+            0b010011_10100_00000_00000_0000010000_0, // blr
+        ];
+        for (index, opcode) in code.iter().enumerate() {
+            gekko.write_u32(BASE_RW_ADRESS + index as u32 * 4, *opcode);
+        }
+        gekko.register.set_gpr(12, 0xFFFF_FFFF);
+        gekko.register.ctr = 50;
+        gekko.break_on_exception.insert(ExceptionKind::Dsi);
+        let break_data = gekko.run_until_event();
+        let gprs: Vec<u32> = (0..32).map(|nb| gekko.register.get_gpr(nb)).collect();
+        let memory = (
+            gekko.read_u32(BASE_RW_ADRESS + 0x200),
+            gekko.read_u8(BASE_RW_ADRESS + 0x300),
+        );
+        let state = (gekko.register.pc, gekko.register.lr, gekko.register.cr);
+        (break_data, gprs, memory, state, gekko.counter)
+    };
+    let (break_data, gprs, memory, state, counter) = run(true);
+    assert_eq!(
+        break_data,
+        BreakData::Exception(Exception::Dsi {
+            address: 0x7000_0000,
+            store: false,
+            protection: false
+        })
+    );
+    assert_eq!(gprs[3], 50);
+    assert_eq!(gprs[7], 0x1234 + 50);
+    assert_eq!(gprs[14], 49);
+    assert_eq!(
+        (gprs[15], gprs[16], gprs[17], gprs[19]),
+        (0x1266, 0x66, 0x1266, 0x1266)
+    );
+    assert_eq!(gprs[18], BASE_RW_ADRESS + 0x200);
+    assert_eq!(gprs[22], 7);
+    assert_eq!(memory, (0x1234 + 50, 50));
+    assert_eq!(state.0, BASE_RW_ADRESS + 21 * 4);
+    assert_eq!(state.1, BASE_RW_ADRESS + 20 * 4);
+    assert_eq!((break_data, gprs, memory, state, counter), run(false));
+}
+
+#[test]
+fn test_alignment_dsisr() {
+    let mut gekko = GekkoInterpreter::new(16);
+    //test "stmw r29, 1(r3)" and "stwcx. r4, r3, r5", both unaligned
+    gekko.write_u32(BASE_RW_ADRESS, 0b101111_11101_00011_00000000_00000001);
+    gekko.write_u32(BASE_RW_ADRESS + 4, 0b011111_00100_00011_00101_0010010110_1);
+    gekko.register.set_gpr(3, BASE_RW_ADRESS);
+    gekko.register.set_gpr(5, 2);
+    let msr = gekko.register.msr;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x600);
+    assert_eq!(gekko.register.dar, BASE_RW_ADRESS + 1);
+    // 0 and bits 5 and 1-4 of the D-form opcode, then rS and rA
+    assert_eq!(gekko.register.dsisr, (0b0010111 << 10) | (29 << 5) | 3);
+    gekko.register.msr = msr;
+    gekko.register.pc = BASE_RW_ADRESS + 4;
+    gekko.step().unwrap();
+    assert_eq!(gekko.register.pc, 0x600);
+    assert_eq!(gekko.register.dar, BASE_RW_ADRESS + 2);
+    // bits 29-30, 25 and 21-24 of the X-form opcode
+    assert_eq!(gekko.register.dsisr, (0b1000010 << 10) | (4 << 5) | 3);
+}
@@ -0,0 +1,132 @@
+/// frequency of the Gekko core, in Hz
+pub const GEKKO_CLOCK: u64 = 486_000_000;
+/// frequency of the bus of the GameCube, in Hz
+pub const BUS_CLOCK: u64 = 162_000_000;
+
+/// The time base and the decrementer. Both are incremented (or decremented) once every four bus
+/// cycles. The CPU is considered to run one instruction per cycle, so the number of CPU cycle
+/// is GekkoInterpreter::counter.
+#[derive(Clone)]
+pub struct TimeBase {
+    // number of CPU cycles per bus cycle
+    clock_ratio: u64,
+    // difference between the time base and the number of elapsed ticks
+    timebase_offset: u64,
+    // value written to the decrementer, and the tick when it was written
+    decrementer_value: u32,
+    decrementer_written_at: u64,
+    // the tick when the decrementer goes from 0 to -1, if it is still to come
+    decrementer_underflow_at: Option<u64>,
+}
+
+impl Default for TimeBase {
+    fn default() -> Self {
+        Self {
+            clock_ratio: GEKKO_CLOCK / BUS_CLOCK,
+            timebase_offset: 0,
+            decrementer_value: 0,
+            decrementer_written_at: 0,
+            decrementer_underflow_at: None,
+        }
+    }
+}
+
+impl TimeBase {
+    /// number of time base ticks since the start, not counting any write to the time base
+    #[inline]
+    fn get_ticks(&self, counter: u64) -> u64 {
+        counter / (self.clock_ratio * 4)
+    }
+
+    pub fn get_clock_ratio(&self) -> u64 {
+        self.clock_ratio
+    }
+
+    /// change the number of CPU cycles per bus cycle. The time base and the decrementer keep
+    /// their current value.
+    pub fn set_clock_ratio(&mut self, counter: u64, clock_ratio: u64) {
+        assert!(clock_ratio > 0);
+        let timebase = self.get_timebase(counter);
+        let decrementer = self.get_decrementer(counter);
+        self.clock_ratio = clock_ratio;
+        self.set_timebase(counter, timebase);
+        // the elapsed ticks are counted differently with the new ratio
+        let ticks = self.get_ticks(counter);
+        self.decrementer_value = decrementer;
+        self.decrementer_written_at = ticks;
+        if self.decrementer_underflow_at.is_some() {
+            self.decrementer_underflow_at = Some(ticks + decrementer as u64 + 1);
+        };
+    }
+
+    #[inline]
+    pub fn get_timebase(&self, counter: u64) -> u64 {
+        self.get_ticks(counter).wrapping_add(self.timebase_offset)
+    }
+
+    pub fn set_timebase(&mut self, counter: u64, value: u64) {
+        self.timebase_offset = value.wrapping_sub(self.get_ticks(counter));
+    }
+
+    /// set the lower 32 bits of the time base, like mtspr TBL
+    pub fn set_tbl(&mut self, counter: u64, value: u32) {
+        let timebase = self.get_timebase(counter);
+        self.set_timebase(counter, (timebase & 0xFFFF_FFFF_0000_0000) | value as u64);
+    }
+
+    /// set the upper 32 bits of the time base, like mtspr TBU
+    pub fn set_tbu(&mut self, counter: u64, value: u32) {
+        let timebase = self.get_timebase(counter);
+        self.set_timebase(counter, (timebase & 0xFFFF_FFFF) | ((value as u64) << 32));
+    }
+
+    #[inline]
+    pub fn get_decrementer(&self, counter: u64) -> u32 {
+        let elapsed = self
+            .get_ticks(counter)
+            .wrapping_sub(self.decrementer_written_at);
+        self.decrementer_value.wrapping_sub(elapsed as u32)
+    }
+
+    /// set the decrementer, like mtspr DEC. Return true if a decrementer exception should be
+    /// signaled, because the most significant bit went from 0 to 1.
+    pub fn set_decrementer(&mut self, counter: u64, value: u32) -> bool {
+        let old_value = self.get_decrementer(counter);
+        let ticks = self.get_ticks(counter);
+        self.decrementer_value = value;
+        self.decrementer_written_at = ticks;
+        self.decrementer_underflow_at = if value >> 31 == 0 {
+            Some(ticks + value as u64 + 1)
+        } else {
+            None
+        };
+        (old_value >> 31 == 0) && (value >> 31 == 1)
+    }
+
+    /// return true once when the decrementer goes from 0 to -1
+    #[inline]
+    pub fn check_decrementer(&mut self, counter: u64) -> bool {
+        match self.decrementer_underflow_at {
+            Some(underflow_at) if self.get_ticks(counter) >= underflow_at => {
+                self.decrementer_underflow_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn test_clock_ratio() {
+    let mut time = TimeBase::default();
+    assert_eq!(time.get_timebase(12 * 10), 10);
+    time.set_decrementer(12 * 10, 100);
+    time.set_clock_ratio(12 * 20, 1);
+    assert_eq!(time.get_timebase(12 * 20), 20);
+    assert_eq!(time.get_decrementer(12 * 20), 90);
+    assert_eq!(time.get_timebase(12 * 20 + 4 * 5), 25);
+    assert_eq!(time.get_decrementer(12 * 20 + 4 * 5), 85);
+    assert!(!time.check_decrementer(12 * 20 + 4 * 90));
+    assert!(time.check_decrementer(12 * 20 + 4 * 91));
+    assert!(!time.check_decrementer(12 * 20 + 4 * 92));
+}
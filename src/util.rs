@@ -58,16 +58,29 @@ pub fn raw_u64_to_f64(value: u64) -> f64 {
     f64::from_ne_bytes(value.to_ne_bytes())
 }
 
+#[inline]
+pub fn is_signaling_nan(value: f64) -> bool {
+    value.is_nan() && (value.to_bits() & 0x0008_0000_0000_0000) == 0
+}
+
 #[inline]
 pub fn get_size_for_quantized_type(size: u8) -> u32 {
     match size {
-        4 | 6 => 10,
-        5 | 7 => 20,
+        4 | 6 => 1,
+        5 | 7 => 2,
         _ => 4,
     }
 }
 
+/// return 2^scale, where scale is the signed 6 bits scale field of a GQR
+#[inline]
+pub fn get_quantization_scale(scale: u8) -> f64 {
+    let scale = ((scale << 2) as i8) >> 2;
+    2.0f64.powi(scale as i32)
+}
+
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_get_bit() {
     assert_eq!(
         get_bit_section(0b00001111_10000000_00000000_00000000, 4, 5),
@@ -77,6 +90,14 @@ fn test_get_bit() {
     assert_eq!(get_bit_value(0x00010000, 15), true);
 }
 
+#[test]
+fn test_get_quantization_scale() {
+    assert_eq!(get_quantization_scale(0), 1.0);
+    assert_eq!(get_quantization_scale(3), 8.0);
+    assert_eq!(get_quantization_scale(0b111111), 0.5);
+    assert_eq!(get_quantization_scale(0b100000), 2.0f64.powi(-32));
+}
+
 #[test]
 fn test_extend_sign() {
     assert_eq!(